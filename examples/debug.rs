@@ -1,5 +1,7 @@
-﻿use page_table::{MmuMeta, PageTable, PageTableFormatter, VmFlags, VmMeta, PPN, VPN};
-use std::ptr::NonNull;
+﻿use page_table::{
+    IdentityMapper, MmuMeta, PAddr, PageTableFormatter, PageTableMut, Pte, RegionFormatter,
+    VmFlags, VmMeta, PPN, VPN,
+};
 
 /// 按 Sv39 的方案修饰任意架构的用户态指针有概率出问题。需要重写测例，支持更好的虚拟化。
 fn main() {
@@ -8,12 +10,17 @@ fn main() {
     const ROP_FLAGS: VmFlags<Sv39> = unsafe { VmFlags::from_raw(0b0011) };
 
     #[repr(C, align(4096))]
-    struct Page([u8; 1 << Sv39::PAGE_BITS]);
+    struct Page([Pte<Sv39>; 512]);
 
     impl Page {
         #[inline]
         pub const fn new() -> Self {
-            Self([0; 1 << Sv39::PAGE_BITS])
+            Self([Pte::ZERO; 512])
+        }
+
+        #[inline]
+        fn ppn(&self) -> PPN<Sv39> {
+            PAddr::new(self.0.as_ptr() as _).floor()
         }
     }
 
@@ -23,48 +30,41 @@ fn main() {
     let mut pt1g7 = Page::new();
     let mut pt2m4 = Page::new();
 
-    let mut root = unsafe {
-        PageTable::<Sv39>::from_raw_parts(
-            NonNull::new_unchecked(root.0.as_mut_ptr().cast()),
-            VPN::ZERO,
-            Sv39::MAX_LEVEL,
-        )
-    };
-    root[0] = SUB_FLAGS.build_pte(PPN::new(pt1g0.0.as_ptr() as usize >> Sv39::PAGE_BITS));
-    root[7] = SUB_FLAGS.build_pte(PPN::new(pt1g7.0.as_ptr() as usize >> Sv39::PAGE_BITS));
-    root[9] = SUB_FLAGS.build_pte(PPN::new(pt1g9.0.as_ptr() as usize >> Sv39::PAGE_BITS));
+    let ppn1g0 = pt1g0.ppn();
+    let ppn1g7 = pt1g7.ppn();
+    let ppn1g9 = pt1g9.ppn();
+    let ppn2m4 = pt2m4.ppn();
 
-    let mut pt1g7 = unsafe {
-        PageTable::<Sv39>::from_raw_parts(
-            NonNull::new_unchecked(pt1g7.0.as_mut_ptr().cast()),
-            VPN::ZERO,
-            Sv39::MAX_LEVEL,
-        )
-    };
+    let mut root = PageTableMut::new(&mut root.0, VPN::ZERO, Sv39::MAX_LEVEL).unwrap();
+    root[0] = SUB_FLAGS.build_pte(ppn1g0);
+    root[7] = SUB_FLAGS.build_pte(ppn1g7);
+    root[9] = SUB_FLAGS.build_pte(ppn1g9);
+
+    let mut pt1g7 = PageTableMut::new(&mut pt1g7.0, VPN::new(7 << 18), 1).unwrap();
     pt1g7[0] = ROP_FLAGS.build_pte(PPN::new(0x12345678));
-    pt1g7[4] = SUB_FLAGS.build_pte(PPN::new(pt2m4.0.as_ptr() as usize >> Sv39::PAGE_BITS));
+    pt1g7[4] = SUB_FLAGS.build_pte(ppn2m4);
 
-    let mut pt2m4 = unsafe {
-        PageTable::<Sv39>::from_raw_parts(
-            NonNull::new_unchecked(pt2m4.0.as_mut_ptr().cast()),
-            VPN::ZERO,
-            Sv39::MAX_LEVEL,
-        )
-    };
+    let mut pt2m4 = PageTableMut::new(&mut pt2m4.0, VPN::new((7 << 18) | (4 << 9)), 0).unwrap();
     for i in 12..18 {
-        pt2m4[i] = XRP_FLAGS.build_pte(PPN::new(0x23300 + i as usize));
+        pt2m4[i] = XRP_FLAGS.build_pte(PPN::new(0x23300 + i as u64));
     }
     for i in 31..40 {
-        pt2m4[i] = ROP_FLAGS.build_pte(PPN::new(0x23300 + i as usize));
+        pt2m4[i] = ROP_FLAGS.build_pte(PPN::new(0x23300 + i as u64));
     }
 
+    let root = root.as_ref();
     println!(
         "{:?}",
         PageTableFormatter {
             pt: root,
-            f: |ppn| unsafe {
-                NonNull::new_unchecked(VPN::<Sv39>::new(ppn.val()).base().as_mut_ptr())
-            }
+            mapper: IdentityMapper,
+        }
+    );
+    println!(
+        "{:?}",
+        RegionFormatter {
+            pt: root,
+            mapper: IdentityMapper,
         }
     );
 }
@@ -73,18 +73,19 @@ fn main() {
 pub(crate) struct Sv39;
 
 impl MmuMeta for Sv39 {
+    type Raw = u64;
     const P_ADDR_BITS: usize = 56;
     const PAGE_BITS: usize = 12;
     const LEVEL_BITS: &'static [usize] = &[9; 3];
     const PPN_POS: usize = 10;
 
     #[inline]
-    fn is_leaf(value: usize) -> bool {
-        const MASK: usize = 0b1110;
+    fn is_leaf(value: u64) -> bool {
+        const MASK: u64 = 0b1110;
         value & MASK != 0
     }
 
-    fn fmt_flags(f: &mut core::fmt::Formatter, flags: usize) -> core::fmt::Result {
+    fn fmt_flags(f: &mut core::fmt::Formatter, flags: u64) -> core::fmt::Result {
         const FLAGS: [u8; 8] = [b'V', b'R', b'W', b'X', b'U', b'G', b'A', b'D'];
         for (i, w) in FLAGS.iter().enumerate().rev() {
             if (flags >> i) & 1 == 1 {
@@ -2,13 +2,13 @@
 use core::{
     fmt,
     marker::PhantomData,
-    ops::{Add, AddAssign, Range},
+    ops::{Add, AddAssign, Range, Sub, SubAssign},
 };
 
 /// 页号。
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
-pub struct PageNumber<Meta: VmMeta, S: Space>(usize, PhantomData<Meta>, PhantomData<S>);
+pub struct PageNumber<Meta: VmMeta, S: Space>(u64, PhantomData<Meta>, PhantomData<S>);
 
 /// 地址空间标记。
 pub trait Space: Clone + Copy + PartialEq + Eq + PartialOrd + Ord + fmt::Debug {}
@@ -33,29 +33,29 @@ impl<Meta: VmMeta, S: Space> PageNumber<Meta, S> {
 
     /// 新建一个页号。
     #[inline]
-    pub const fn new(n: usize) -> Self {
+    pub const fn new(n: u64) -> Self {
         Self(n, PhantomData, PhantomData)
     }
 
     /// 获取页号值。
     #[inline]
-    pub const fn val(self) -> usize {
+    pub const fn val(self) -> u64 {
         self.0
     }
 }
 
-impl<Meta: VmMeta, S: Space> Add<usize> for PageNumber<Meta, S> {
+impl<Meta: VmMeta, S: Space> Add<u64> for PageNumber<Meta, S> {
     type Output = Self;
 
     #[inline]
-    fn add(self, rhs: usize) -> Self {
+    fn add(self, rhs: u64) -> Self {
         Self::new(self.0.wrapping_add(rhs))
     }
 }
 
-impl<Meta: VmMeta, S: Space> AddAssign<usize> for PageNumber<Meta, S> {
+impl<Meta: VmMeta, S: Space> AddAssign<u64> for PageNumber<Meta, S> {
     #[inline]
-    fn add_assign(&mut self, rhs: usize) {
+    fn add_assign(&mut self, rhs: u64) {
         self.0 = self.0.wrapping_add(rhs);
     }
 }
@@ -69,13 +69,21 @@ pub type VPN<Meta> = PageNumber<Meta, Virtual>;
 impl<Meta: VmMeta> PPN<Meta> {
     /// 无效的物理页号，作为 NULL 使用。
     ///
-    /// 显然，物理页号不可能和 usize 一样长，所以可以这样操作。
-    pub const INVALID: Self = Self::new(1 << (Meta::P_ADDR_BITS - Meta::PAGE_BITS));
+    /// 显然，物理页号不可能和 u64 一样长，所以可以这样操作。
+    pub const INVALID: Self = Self::new(1 << ppn_bits::<Meta>());
 
     /// 最大物理页号。
     pub const MAX: Self = Self::new(Self::INVALID.val() - 1);
 }
 
+impl<Meta: VmMeta> PPN<Meta> {
+    /// 物理页的起始地址。
+    #[inline]
+    pub const fn base(self) -> PAddr<Meta> {
+        PAddr::new(self.0 << Meta::PAGE_BITS)
+    }
+}
+
 impl<Meta: VmMeta> fmt::Debug for PPN<Meta> {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -96,7 +104,7 @@ impl<Meta: VmMeta> VPN<Meta> {
     /// 虚页在 `level` 级页表中的位置。
     #[inline]
     pub fn index_in(self, level: usize) -> usize {
-        (self.0 >> Self::bits_until(level)) & mask(Meta::LEVEL_BITS[level])
+        ((self.0 >> Self::bits_until(level)) & mask(Meta::LEVEL_BITS[level])) as _
     }
 
     /// 包含这个虚页的 `level` 级页表起始地址。
@@ -108,7 +116,7 @@ impl<Meta: VmMeta> VPN<Meta> {
 
     /// 不包含这个虚页的 `level` 级页表起始地址。
     #[inline]
-    pub fn ceil(self, level: usize) -> usize {
+    pub fn ceil(self, level: usize) -> u64 {
         let bits = Self::bits_until(level);
         (self.0 + mask(bits)) >> bits
     }
@@ -147,6 +155,18 @@ impl<Meta: VmMeta> fmt::Debug for VPN<Meta> {
     }
 }
 
+/// 物理页号的有效位数，不超过 `u64` 的位数减一。
+#[inline]
+const fn ppn_bits<Meta: VmMeta>() -> usize {
+    let bits = Meta::P_ADDR_BITS - Meta::PAGE_BITS;
+    let max = u64::BITS as usize - 1;
+    if bits < max {
+        bits
+    } else {
+        max
+    }
+}
+
 /// 一个可能无效的物理页号。
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
@@ -176,40 +196,132 @@ impl<Meta: VmMeta> MaybeInvalidPPN<Meta> {
     }
 }
 
+/// 物理地址。
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PAddr<Meta: VmMeta>(u64, PhantomData<Meta>);
+
+impl<Meta: VmMeta> PAddr<Meta> {
+    /// 将一个地址值转换为物理地址。超过物理地址位数的部分会被裁剪。
+    #[inline]
+    pub const fn new(value: u64) -> Self {
+        Self(value & mask(Meta::P_ADDR_BITS), PhantomData)
+    }
+
+    /// 物理地址值。
+    #[inline]
+    pub const fn val(self) -> u64 {
+        self.0
+    }
+
+    /// 包括这个物理地址最后页的页号。
+    #[inline]
+    pub const fn floor(self) -> PPN<Meta> {
+        PPN::new(self.0 >> Meta::PAGE_BITS)
+    }
+
+    /// 不包括这个物理地址的最前页的页号。
+    #[inline]
+    pub const fn ceil(self) -> PPN<Meta> {
+        PPN::new((self.0 + mask(Meta::PAGE_BITS)) >> Meta::PAGE_BITS)
+    }
+
+    /// 页内偏移。
+    #[inline]
+    pub const fn offset(self) -> u64 {
+        self.0 & mask(Meta::PAGE_BITS)
+    }
+}
+
+impl<Meta: VmMeta> Add<u64> for PAddr<Meta> {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self {
+        Self::new(self.0.wrapping_add(rhs))
+    }
+}
+
+impl<Meta: VmMeta> AddAssign<u64> for PAddr<Meta> {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl<Meta: VmMeta> Sub<u64> for PAddr<Meta> {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self {
+        Self::new(self.0.wrapping_sub(rhs))
+    }
+}
+
+impl<Meta: VmMeta> SubAssign<u64> for PAddr<Meta> {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+impl<Meta: VmMeta> Sub for PAddr<Meta> {
+    type Output = u64;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> u64 {
+        self.0.wrapping_sub(rhs.0)
+    }
+}
+
+impl<Meta: VmMeta> From<usize> for PAddr<Meta> {
+    #[inline]
+    fn from(value: usize) -> Self {
+        Self::new(value as _)
+    }
+}
+
+impl<Meta: VmMeta> fmt::Debug for PAddr<Meta> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PAddr({:#x})", self.0)
+    }
+}
+
 /// 虚拟地址。
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
-pub struct VAddr<Meta: VmMeta>(usize, PhantomData<Meta>);
+pub struct VAddr<Meta: VmMeta>(u64, PhantomData<Meta>);
 
-impl<Meta: VmMeta> Add<usize> for VAddr<Meta> {
+impl<Meta: VmMeta> Add<u64> for VAddr<Meta> {
     type Output = Self;
 
     #[inline]
-    fn add(self, rhs: usize) -> Self {
+    fn add(self, rhs: u64) -> Self {
         Self::new(self.0.wrapping_add(rhs))
     }
 }
 
-impl<Meta: VmMeta> AddAssign<usize> for VAddr<Meta> {
+impl<Meta: VmMeta> AddAssign<u64> for VAddr<Meta> {
     #[inline]
-    fn add_assign(&mut self, rhs: usize) {
+    fn add_assign(&mut self, rhs: u64) {
         self.0 = self.0.wrapping_add(rhs);
     }
 }
 
 impl<Meta: VmMeta> VAddr<Meta> {
-    const IGNORED_MASK: usize = mask(Meta::V_ADDR_BITS - 1);
+    const IGNORED_MASK: u64 = mask(Meta::V_ADDR_BITS - 1);
 
     /// 将一个地址值转换为虚拟地址意味着允许虚存方案根据实际情况裁剪地址值。
     /// 超过虚址范围的地址会被裁剪。
     #[inline]
-    pub const fn new(value: usize) -> Self {
+    pub const fn new(value: u64) -> Self {
         Self(value & mask(Meta::V_ADDR_BITS), PhantomData)
     }
 
     /// 虚地址值。
     #[inline]
-    pub const fn val(self) -> usize {
+    pub const fn val(self) -> u64 {
         if self.0 <= Self::IGNORED_MASK {
             self.0
         } else {
@@ -224,7 +336,7 @@ impl<Meta: VmMeta> VAddr<Meta> {
     /// 调用者需要确保虚地址在当前地址空间中。
     #[inline]
     pub const unsafe fn as_ptr<T>(self) -> *const T {
-        self.val() as _
+        self.val() as usize as _
     }
 
     /// 将虚地址转化为任意可变指针。
@@ -234,7 +346,7 @@ impl<Meta: VmMeta> VAddr<Meta> {
     /// 调用者需要确保虚地址在当前地址空间中。
     #[inline]
     pub unsafe fn as_mut_ptr<T>(self) -> *mut T {
-        self.val() as _
+        self.val() as usize as _
     }
 
     /// 包括这个虚地址最后页的页号。
@@ -251,7 +363,7 @@ impl<Meta: VmMeta> VAddr<Meta> {
 
     /// 页内偏移。
     #[inline]
-    pub const fn offset(self) -> usize {
+    pub const fn offset(self) -> u64 {
         self.0 & mask(Meta::PAGE_BITS)
     }
 }
@@ -259,14 +371,14 @@ impl<Meta: VmMeta> VAddr<Meta> {
 impl<Meta: VmMeta> From<usize> for VAddr<Meta> {
     #[inline]
     fn from(value: usize) -> Self {
-        Self::new(value)
+        Self::new(value as _)
     }
 }
 
 impl<Meta: VmMeta, T> From<&T> for VAddr<Meta> {
     #[inline]
     fn from(value: &T) -> Self {
-        Self::new(value as *const _ as _)
+        Self::new(value as *const _ as usize as _)
     }
 }
 
@@ -277,6 +389,25 @@ impl<Meta: VmMeta> fmt::Debug for VAddr<Meta> {
     }
 }
 
+#[test]
+fn test_paddr() {
+    use crate::test_meta::Sv39;
+
+    let addr = PAddr::<Sv39>::new(0x8020_1234);
+    assert_eq!(addr.floor(), PPN::new(0x80201));
+    assert_eq!(addr.ceil(), PPN::new(0x80202));
+    assert_eq!(addr.offset(), 0x234);
+    assert_eq!(addr.floor().base() + addr.offset(), addr);
+    assert_eq!((addr - 0x1234).floor().base(), PAddr::new(0x8020_0000));
+    assert_eq!(addr - PAddr::new(0x8000_0000), 0x20_1234);
+    assert_eq!(PAddr::<Sv39>::new(u64::MAX).val(), (1 << 56) - 1);
+
+    // Sv32 的物理地址有 34 位，在 32 位平台上也不能截断
+    let addr = PAddr::<crate::riscv::Sv32>::new(0x3_8020_1234);
+    assert_eq!(addr.floor(), PPN::new(0x38_0201));
+    assert_eq!(PPN::<crate::riscv::Sv32>::MAX.base().val(), 0x3_ffff_f000);
+}
+
 #[test]
 fn test_index_in() {
     use crate::test_meta::Sv39;
@@ -298,3 +429,13 @@ fn test_align_level() {
     assert_eq!(VPN::<Sv39>::new(1 << 18).align_level(), 2);
     assert_eq!(VPN::<Sv39>::new(0).align_level(), 2);
 }
+
+#[test]
+fn test_wide_ppn() {
+    use crate::{riscv::Sv39, VmFlags};
+
+    // 物理页号超过 32 位时，在 32 位平台上也不能截断
+    let ppn = PPN::<Sv39>::new(0xab_cdef_0123);
+    assert_eq!(VmFlags::VALID.build_pte(ppn).ppn(), ppn);
+    assert_eq!(PPN::<Sv39>::MAX.val(), (1 << 44) - 1);
+}
@@ -1 +1,224 @@
-﻿// TODO
+﻿//! AArch64 VMSAv8-64 第一阶段转换，4 KiB 粒度，48 位虚址。
+//!
+//! see <https://developer.arm.com/documentation/ddi0487/latest>.
+
+use crate::VmFlags;
+
+/// AArch64 VMSAv8-64 第一阶段转换方案，使用 4 KiB 粒度和 48 位虚址。
+///
+/// 本库的 3 级页表对应架构手册中的第 0 级转换表，0 级页表对应第 3 级转换表。
+///
+/// 描述符的类型由 bits[1:0] 决定：`0b01` 是块描述符，`0b11` 在 0 级页表中是页描述符，在其他级页表中是表描述符。
+/// 因此只有 1 级（2 MiB）和 2 级（1 GiB）页表项可以成为大页，0 级页表项总是指向物理页。
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Vmsav8;
+
+impl crate::MmuMeta for Vmsav8 {
+    type Raw = u64;
+    const P_ADDR_BITS: usize = 48;
+    const PAGE_BITS: usize = 12;
+    const LEVEL_BITS: &'static [usize] = &[9; 4];
+    const PPN_POS: usize = 12;
+    const TABLE_FLAGS: u64 = TABLE_OR_PAGE;
+    const COW_FLAG: u64 = COW;
+    const SHARED_FLAG: u64 = SHARED;
+    const ACCESSED_FLAG: u64 = AF;
+
+    #[inline]
+    fn is_leaf(value: u64) -> bool {
+        value & TYPE_MASK == BLOCK
+    }
+
+    #[inline]
+    fn leaf_flags(flags: u64, level: usize) -> u64 {
+        let ty = if level == 0 { TABLE_OR_PAGE } else { BLOCK };
+        (flags & !TYPE_MASK) | ty
+    }
+
+    /// AP[2] 置位表示只读。
+    #[inline]
+    fn is_writable(flags: u64) -> bool {
+        flags & AP_RO == 0
+    }
+
+    #[inline]
+    fn set_writable(flags: u64, writable: bool) -> u64 {
+        if writable {
+            flags & !AP_RO
+        } else {
+            flags | AP_RO
+        }
+    }
+
+    /// 4 kiB 粒度下只有 2 MiB 和 1 GiB 块。
+    #[inline]
+    fn allow_huge(level: usize) -> bool {
+        level <= 2
+    }
+
+    fn fmt_flags(f: &mut core::fmt::Formatter, flags: u64) -> core::fmt::Result {
+        let bit = |name: &'static str, pos: u64| if (flags >> pos) & 1 == 1 { name } else { "___" };
+        let sh = match (flags >> SH_POS) & 0b11 {
+            0b00 => "NS",
+            0b10 => "OS",
+            0b11 => "IS",
+            _ => "??",
+        };
+        let ap = match (flags >> AP_POS) & 0b11 {
+            0b00 => "rw--",
+            0b01 => "rwrw",
+            0b10 => "r---",
+            _ => "r-r-",
+        };
+        write!(
+            f,
+            "{} {} {:.2} {:.2} {sh} {ap} Attr{}",
+            bit("UXN", 54),
+            bit("PXN", 53),
+            bit("nG", 11),
+            bit("AF", 10),
+            (flags >> ATTR_INDEX_POS) & 0b111,
+        )
+    }
+}
+
+/// 描述符类型掩码。
+const TYPE_MASK: u64 = 0b11;
+/// 块描述符类型。
+const BLOCK: u64 = 0b01;
+/// 页描述符或表描述符类型。
+const TABLE_OR_PAGE: u64 = 0b11;
+/// AttrIndx[2:0] 的位置。
+const ATTR_INDEX_POS: usize = 2;
+/// AP[2:1] 的位置。
+const AP_POS: usize = 6;
+/// AP[2]，只读。
+const AP_RO: u64 = 1 << 7;
+/// AF，访问标志。没有硬件管理时，访问 AF 为零的页会触发访问标志异常。
+const AF: u64 = 1 << 10;
+/// 写时复制位，使用第一个软件保留位。
+const COW: u64 = 1 << 55;
+/// 共享页表项标志位，使用第二个软件保留位，页表描述符中同样被忽略。
+const SHARED: u64 = 1 << 56;
+/// SH[1:0] 的位置。
+const SH_POS: usize = 8;
+
+impl VmFlags<Vmsav8> {
+    /// 表描述符，指向子页表。
+    pub const TABLE: Self = unsafe { Self::from_raw(TABLE_OR_PAGE) };
+
+    /// 页描述符，只能用于 0 级页表项。
+    pub const PAGE: Self = unsafe { Self::from_raw(TABLE_OR_PAGE) };
+
+    /// 块描述符，只能用于 1、2 级页表项。
+    pub const BLOCK: Self = unsafe { Self::from_raw(BLOCK) };
+
+    /// 访问标志（AF）。
+    pub const AF: Self = unsafe { Self::from_raw(AF) };
+
+    /// 非全局标志（nG）。
+    pub const NG: Self = unsafe { Self::from_raw(1 << 11) };
+
+    /// 特权级不可执行（PXN）。
+    pub const PXN: Self = unsafe { Self::from_raw(1 << 53) };
+
+    /// 用户级不可执行（UXN）。
+    pub const UXN: Self = unsafe { Self::from_raw(1 << 54) };
+
+    /// 合并两组属性。
+    ///
+    /// 编译期版本的 `|`。
+    #[inline]
+    pub const fn union(self, other: Self) -> Self {
+        unsafe { Self::from_raw(self.val() | other.val()) }
+    }
+
+    /// 设置 MAIR_ELx 中的内存属性序号（AttrIndx[2:0]）。
+    #[inline]
+    pub const fn attr_index(self, index: usize) -> Self {
+        self.set_field(ATTR_INDEX_POS, 0b111, index)
+    }
+
+    /// 设置访问权限（AP[2:1]）。
+    ///
+    /// - `0b00`：特权级可读写，用户级不可访问；
+    /// - `0b01`：特权级和用户级都可读写；
+    /// - `0b10`：特权级只读，用户级不可访问；
+    /// - `0b11`：特权级和用户级都只读；
+    #[inline]
+    pub const fn access_permission(self, ap: usize) -> Self {
+        self.set_field(AP_POS, 0b11, ap)
+    }
+
+    /// 设置可共享属性（SH[1:0]）。
+    ///
+    /// - `0b00`：不可共享；
+    /// - `0b10`：外部可共享；
+    /// - `0b11`：内部可共享；
+    #[inline]
+    pub const fn shareability(self, sh: usize) -> Self {
+        self.set_field(SH_POS, 0b11, sh)
+    }
+
+    #[inline]
+    const fn set_field(self, pos: usize, mask: u64, value: usize) -> Self {
+        let value = value as u64 & mask;
+        unsafe { Self::from_raw((self.val() & !(mask << pos)) | (value << pos)) }
+    }
+}
+
+mod assertions {
+    use super::*;
+    use crate::{MmuMeta, VmMeta};
+    use static_assertions::const_assert_eq;
+
+    const_assert_eq!(Vmsav8::V_ADDR_BITS, 48);
+    const_assert_eq!(Vmsav8::MAX_LEVEL, 3);
+    const_assert_eq!(Vmsav8::PAGE_BITS, 12);
+    const_assert_eq!(Vmsav8::PPN_MASK, 0x0000_ffff_ffff_f000);
+
+    const_assert_eq!(
+        VmFlags::<Vmsav8>::PAGE
+            .union(VmFlags::AF)
+            .attr_index(1)
+            .access_permission(0b01)
+            .shareability(0b11)
+            .val(),
+        0b111_0100_0111
+    );
+}
+
+#[test]
+fn test_collapse() {
+    use crate::{
+        test_meta::{root_table, Frames},
+        IdentityMapper, Mapping, PageTableMut, PPN, VPN,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Vmsav8> = unsafe { root_table(&mut frames) };
+    // 0 级页表项是页描述符，形式与表描述符相同
+    let page = VmFlags::<Vmsav8>::PAGE.union(VmFlags::AF);
+    pt.map(
+        VPN::new(0x200)..VPN::new(0x400),
+        PPN::new(0x8000),
+        page,
+        0,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    assert_eq!(frames.used(), 4);
+
+    assert_eq!(
+        pt.try_collapse(VPN::new(0x234), 1, &mut frames, &IdentityMapper),
+        Ok(true)
+    );
+    assert_eq!(frames.used(), 3);
+    assert!(pt.mappings(&IdentityMapper).eq([Mapping {
+        vpn: VPN::new(0x200),
+        ppn: PPN::new(0x8000),
+        flags: VmFlags::BLOCK.union(VmFlags::AF),
+        level: 1,
+    }]));
+}
@@ -1,90 +1,136 @@
-﻿use core::str::FromStr;
-
-use crate::VmFlags;
-
-cfg_if::cfg_if! {
-    if #[cfg(target_pointer_width = "32")] {
-        /// 32 位 RISC-V 物理地址位数。
-        const P_ADDR_BITS: usize = 34;
-        /// RISC-V Sv32 VM Mode.
-        pub type Sv32 = Sv<2>;
-    } else if #[cfg(target_pointer_width = "64")] {
-        /// 64 位 RISC-V 物理地址位数。
-        const P_ADDR_BITS: usize = 56;
-        /// RISC-V Sv39 VM Mode.
-        pub type Sv39 = Sv<3>;
-        /// RISC-V Sv48 VM Mode.
-        pub type Sv48 = Sv<4>;
-        /// RISC-V Sv57 VM Mode.
-        pub type Sv57 = Sv<5>;
-    }
-}
+﻿//! RISC-V 标准定义的虚存方案。
+//!
+//! see <https://github.com/riscv/riscv-isa-manual/releases>.
+
+use core::str::FromStr;
+
+use crate::{PageTableError, RawPte, VmFlags, VmMeta};
+
+/// RISC-V Sv32 VM Mode.
+pub type Sv32 = Sv<2>;
+/// RISC-V Sv39 VM Mode.
+pub type Sv39 = Sv<3>;
+/// RISC-V Sv48 VM Mode.
+pub type Sv48 = Sv<4>;
+/// RISC-V Sv57 VM Mode.
+pub type Sv57 = Sv<5>;
 
 /// RISC-V 标准定义的虚存方案。
+///
+/// 只有标准定义的 `N` 实现了 [`MmuMeta`](crate::MmuMeta)，使用其他 `N` 无法通过编译：
+///
+/// | `N` | 方案 | 物理地址位数 | 页表项字节数
+/// |:-:|:-:|:-:|:-:
+/// | 2 | Sv32 | 34 | 4
+/// | 3 | Sv39 | 56 | 8
+/// | 4 | Sv48 | 56 | 8
+/// | 5 | Sv57 | 56 | 8
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Sv<const N: usize>;
 
-impl<const N: usize> crate::MmuMeta for Sv<N> {
-    const P_ADDR_BITS: usize = P_ADDR_BITS;
-    const PAGE_BITS: usize = 12;
-    const LEVEL_BITS: &'static [usize] = &[pt_level_bits(Self::PAGE_BITS); N];
-    const PPN_POS: usize = 10;
+macro_rules! impl_sv {
+    ($n:literal, $raw:ty, $p_addr_bits:literal) => {
+        impl crate::MmuMeta for Sv<$n> {
+            type Raw = $raw;
+            const P_ADDR_BITS: usize = $p_addr_bits;
+            const PAGE_BITS: usize = 12;
+            const LEVEL_BITS: &'static [usize] =
+                &[pt_level_bits(Self::PAGE_BITS, core::mem::size_of::<$raw>()); $n];
+            const PPN_POS: usize = 10;
+            // 使用 RSW 的低位
+            const COW_FLAG: $raw = 1 << 8;
+            // 使用 RSW 的高位
+            const SHARED_FLAG: $raw = 1 << 9;
+            const ACCESSED_FLAG: $raw = 1 << 6;
+
+            #[inline]
+            fn is_leaf(value: $raw) -> bool {
+                const MASK: $raw = 0b1110;
+                value & MASK != 0
+            }
 
-    #[inline]
-    fn is_leaf(value: usize) -> bool {
-        const MASK: usize = 0b1110;
-        value & MASK != 0
-    }
+            #[inline]
+            fn is_writable(flags: $raw) -> bool {
+                const W: $raw = 1 << 2;
+                flags & W != 0
+            }
 
-    #[inline]
-    fn fmt_flags(f: &mut core::fmt::Formatter, flags: usize) -> core::fmt::Result {
-        for (i, w) in FLAGS.iter().enumerate().rev() {
-            if (flags >> i) & 1 == 1 {
-                write!(f, "{}", *w as char)?;
-            } else {
-                write!(f, "_")?;
+            #[inline]
+            fn set_writable(flags: $raw, writable: bool) -> $raw {
+                const W: $raw = 1 << 2;
+                if writable {
+                    flags | W
+                } else {
+                    flags & !W
+                }
+            }
+
+            #[inline]
+            fn fmt_flags(f: &mut core::fmt::Formatter, flags: $raw) -> core::fmt::Result {
+                fmt_flags(f, flags as _)
             }
         }
-        Ok(())
-    }
+
+        impl VmFlags<Sv<$n>> {
+            /// 从字符串构造页属性。
+            ///
+            /// 编译期版本。
+            #[inline]
+            pub const fn build_from_str(s: &str) -> Self {
+                unsafe { Self::from_raw(build_from_str_internal(0, s.as_bytes()) as _) }
+            }
+        }
+    };
 }
-const FLAGS: [u8; 8] = [b'V', b'R', b'W', b'X', b'U', b'G', b'A', b'D'];
 
-impl<const N: usize> VmFlags<Sv<N>> {
-    /// 从字符串构造页属性。
-    ///
-    /// 编译期版本。
-    #[inline]
-    pub const fn build_from_str(s: &str) -> Self {
-        unsafe { Self::from_raw(Self::build_from_str_internal(0, s.as_bytes())) }
+impl_sv!(2, u32, 34);
+impl_sv!(3, u64, 56);
+impl_sv!(4, u64, 56);
+impl_sv!(5, u64, 56);
+
+#[inline]
+fn fmt_flags(f: &mut core::fmt::Formatter, flags: usize) -> core::fmt::Result {
+    for (i, w) in FLAGS.iter().enumerate().rev() {
+        if (flags >> i) & 1 == 1 {
+            write!(f, "{}", *w as char)?;
+        } else {
+            write!(f, "_")?;
+        }
     }
+    Ok(())
+}
 
-    const fn build_from_str_internal(mut base: usize, s: &[u8]) -> usize {
-        match s {
-            [c, tail @ ..] => {
-                if c.is_ascii_alphabetic() {
-                    base |= match c {
-                        b'V' | b'v' => 1 << 0,
-                        b'R' | b'r' => 1 << 1,
-                        b'W' | b'w' => 1 << 2,
-                        b'X' | b'x' => 1 << 3,
-                        b'U' | b'u' => 1 << 4,
-                        b'G' | b'g' => 1 << 5,
-                        b'A' | b'a' => 1 << 6,
-                        b'D' | b'd' => 1 << 7,
-                        _ => 0,
-                    }
+const FLAGS: [u8; 8] = [b'V', b'R', b'W', b'X', b'U', b'G', b'A', b'D'];
+
+const fn build_from_str_internal(mut base: usize, s: &[u8]) -> usize {
+    match s {
+        [c, tail @ ..] => {
+            if c.is_ascii_alphabetic() {
+                base |= match c {
+                    b'V' | b'v' => 1 << 0,
+                    b'R' | b'r' => 1 << 1,
+                    b'W' | b'w' => 1 << 2,
+                    b'X' | b'x' => 1 << 3,
+                    b'U' | b'u' => 1 << 4,
+                    b'G' | b'g' => 1 << 5,
+                    b'A' | b'a' => 1 << 6,
+                    b'D' | b'd' => 1 << 7,
+                    _ => 0,
                 }
-                Self::build_from_str_internal(base, tail)
             }
-            [] => base,
+            build_from_str_internal(base, tail)
         }
+        [] => base,
     }
 }
 
-impl<const N: usize> FromStr for VmFlags<Sv<N>> {
-    type Err = ();
+impl<const N: usize> FromStr for VmFlags<Sv<N>>
+where
+    Sv<N>: VmMeta,
+{
+    type Err = PageTableError;
 
+    /// 从字符串解析页属性，不认识的字母被忽略。
     #[inline]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let flags = s
@@ -93,31 +139,27 @@ impl<const N: usize> FromStr for VmFlags<Sv<N>> {
             .map(|c| c.to_ascii_uppercase())
             .filter_map(|c| FLAGS.iter().position(|x| *x == c))
             .fold(0, |c, i| c | (1 << i));
-        Ok(unsafe { VmFlags::from_raw(flags) })
+        Ok(unsafe { VmFlags::from_raw(RawPte::from_usize(flags)) })
     }
 }
 
+/// 一页能容纳的页表项数量的位数。
 #[inline]
-const fn pt_level_bits(page_bits: usize) -> usize {
-    page_bits - core::mem::size_of::<usize>().trailing_zeros() as usize
+const fn pt_level_bits(page_bits: usize, pte_bytes: usize) -> usize {
+    page_bits - pte_bytes.trailing_zeros() as usize
 }
 
-#[cfg(target_pointer_width = "32")]
 mod assertions {
     use super::*;
-    use crate::{MmuMeta, VmMeta};
+    use crate::MmuMeta;
     use static_assertions::const_assert_eq;
 
     const_assert_eq!(Sv32::V_ADDR_BITS, 32);
     const_assert_eq!(Sv32::MAX_LEVEL, 1);
     const_assert_eq!(Sv32::PAGE_BITS, 12);
-}
-
-#[cfg(target_pointer_width = "64")]
-mod assertions {
-    use super::*;
-    use crate::{MmuMeta, VmMeta};
-    use static_assertions::const_assert_eq;
+    const_assert_eq!(Sv32::LEVEL_BITS[0], 10);
+    const_assert_eq!(Sv32::PPN_MASK, 0xffff_fc00);
+    const_assert_eq!(core::mem::size_of::<crate::Pte<Sv32>>(), 4);
 
     const_assert_eq!(Sv39::V_ADDR_BITS, 39);
     const_assert_eq!(Sv48::V_ADDR_BITS, 48);
@@ -130,4 +172,6 @@ mod assertions {
     const_assert_eq!(Sv39::PAGE_BITS, 12);
     const_assert_eq!(Sv48::PAGE_BITS, 12);
     const_assert_eq!(Sv57::PAGE_BITS, 12);
+
+    const_assert_eq!(core::mem::size_of::<crate::Pte<Sv39>>(), 8);
 }
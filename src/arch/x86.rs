@@ -1 +1,368 @@
-﻿//! TODO. see <https://www.intel.com/content/www/us/en/architecture-and-technology/64-ia-32-architectures-software-developer-vol-3c-part-3-manual.html>.
+﻿//! x86_64 4 级分页（IA-32e）。
+//!
+//! see <https://www.intel.com/content/www/us/en/architecture-and-technology/64-ia-32-architectures-software-developer-vol-3c-part-3-manual.html>.
+
+use core::str::FromStr;
+
+use crate::{PageTableError, VmFlags};
+
+/// x86_64 4 级分页方案。
+///
+/// 只有 1 级（2 MiB）和 2 级（1 GiB）页表项可以通过 PS 位成为大页，
+/// 0 级页表项总是指向物理页，其第 7 位是 PAT 而不是 PS。大页页表项的 PAT 位是第 12 位，位于物理页号字段中。
+///
+/// 新建的中间页表项具有 P、RW 和 US 位，访问权限完全由叶子页表项决定。
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct X86_64;
+
+impl crate::MmuMeta for X86_64 {
+    type Raw = u64;
+    const P_ADDR_BITS: usize = 52;
+    const PAGE_BITS: usize = 12;
+    const LEVEL_BITS: &'static [usize] = &[9; 4];
+    const PPN_POS: usize = 12;
+    const TABLE_FLAGS: u64 = 0b111;
+    const COW_FLAG: u64 = COW;
+    const SHARED_FLAG: u64 = SHARED;
+    const ACCESSED_FLAG: u64 = ACCESSED;
+
+    #[inline]
+    fn is_leaf(value: u64) -> bool {
+        value & PS != 0
+    }
+
+    /// 0 级页表项的第 7 位是 PAT，保持不变。
+    #[inline]
+    fn leaf_flags(flags: u64, level: usize) -> u64 {
+        if level == 0 {
+            flags
+        } else {
+            flags | PS
+        }
+    }
+
+    /// 大页页表项的第 12 位是 PAT。
+    #[inline]
+    fn leaf_ppn_flags(level: usize) -> u64 {
+        if level == 0 {
+            0
+        } else {
+            HUGE_PAT
+        }
+    }
+
+    #[inline]
+    fn is_writable(flags: u64) -> bool {
+        flags & RW != 0
+    }
+
+    #[inline]
+    fn set_writable(flags: u64, writable: bool) -> u64 {
+        if writable {
+            flags | RW
+        } else {
+            flags & !RW
+        }
+    }
+
+    /// 只有 2 MiB 和 1 GiB 大页。
+    #[inline]
+    fn allow_huge(level: usize) -> bool {
+        level <= 2
+    }
+
+    #[inline]
+    fn fmt_flags(f: &mut core::fmt::Formatter, flags: u64) -> core::fmt::Result {
+        fmt_names(f, flags, &FLAGS)
+    }
+
+    /// 0 级页表项的第 7 位显示为 PAT，大页页表项另外显示第 12 位的 PAT。
+    #[inline]
+    fn fmt_leaf_flags(f: &mut core::fmt::Formatter, flags: u64, level: usize) -> core::fmt::Result {
+        if level == 0 {
+            fmt_names(f, flags, &PAGE_FLAGS)
+        } else {
+            fmt_names(f, flags, &HUGE_FLAGS)
+        }
+    }
+}
+
+/// 按 `names` 中的名字和位置打印特性位，没有设置的位打印为下划线。
+fn fmt_names(
+    f: &mut core::fmt::Formatter,
+    flags: u64,
+    names: &[(&str, usize)],
+) -> core::fmt::Result {
+    for (i, (name, bit)) in names.iter().enumerate().rev() {
+        if (flags >> bit) & 1 == 1 {
+            write!(f, "{name}")?;
+        } else {
+            for _ in 0..name.len() {
+                write!(f, "_")?;
+            }
+        }
+        if i != 0 {
+            write!(f, " ")?;
+        }
+    }
+    Ok(())
+}
+
+/// 可写位。
+const RW: u64 = 1 << 1;
+/// 访问位。
+const ACCESSED: u64 = 1 << 5;
+/// 大页标志位。
+const PS: u64 = 1 << 7;
+/// 大页页表项的 PAT 位。
+const HUGE_PAT: u64 = 1 << 12;
+/// 写时复制位，使用第一个可供软件使用的位。
+const COW: u64 = 1 << 9;
+/// 共享页表项标志位，使用第二个可供软件使用的位。
+const SHARED: u64 = 1 << 10;
+
+/// 特性位的名字和位置。
+const FLAGS: [(&str, usize); 10] = [
+    ("P", 0),
+    ("RW", 1),
+    ("US", 2),
+    ("PWT", 3),
+    ("PCD", 4),
+    ("A", 5),
+    ("D", 6),
+    ("PS", 7),
+    ("G", 8),
+    ("NX", 63),
+];
+
+/// 0 级页表项特性位的名字和位置。
+const PAGE_FLAGS: [(&str, usize); 10] = [
+    ("P", 0),
+    ("RW", 1),
+    ("US", 2),
+    ("PWT", 3),
+    ("PCD", 4),
+    ("A", 5),
+    ("D", 6),
+    ("PAT", 7),
+    ("G", 8),
+    ("NX", 63),
+];
+
+/// 大页页表项特性位的名字和位置。
+const HUGE_FLAGS: [(&str, usize); 11] = [
+    ("P", 0),
+    ("RW", 1),
+    ("US", 2),
+    ("PWT", 3),
+    ("PCD", 4),
+    ("A", 5),
+    ("D", 6),
+    ("PS", 7),
+    ("G", 8),
+    ("PAT", 12),
+    ("NX", 63),
+];
+
+impl VmFlags<X86_64> {
+    /// 从字符串构造页属性。
+    ///
+    /// 字符串由特性位的名字组成，名字之间以任意非字母字符分隔，不区分大小写，例如 `"P | RW | NX"`。
+    ///
+    /// 编译期版本。
+    #[inline]
+    pub const fn build_from_str(s: &str) -> Self {
+        unsafe { Self::from_raw(Self::build_from_str_internal(0, s.as_bytes())) }
+    }
+
+    const fn build_from_str_internal(base: u64, s: &[u8]) -> u64 {
+        match s {
+            [c, tail @ ..] if !c.is_ascii_alphabetic() => Self::build_from_str_internal(base, tail),
+            [_, ..] => {
+                let (name, tail) = s.split_at(name_len(s, 0));
+                Self::build_from_str_internal(base | flag_of(name), tail)
+            }
+            [] => base,
+        }
+    }
+}
+
+impl FromStr for VmFlags<X86_64> {
+    type Err = PageTableError;
+
+    /// 从字符串解析页属性，不认识的名字被忽略。
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flags = s
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .map(|name| flag_of(name.as_bytes()))
+            .fold(0, |c, bit| c | bit);
+        Ok(unsafe { VmFlags::from_raw(flags) })
+    }
+}
+
+/// 从 `s` 的第 `n` 个字符开始的名字长度。
+const fn name_len(s: &[u8], n: usize) -> usize {
+    if n < s.len() && s[n].is_ascii_alphabetic() {
+        name_len(s, n + 1)
+    } else {
+        n
+    }
+}
+
+/// 名字对应的特性位，未知的名字对应 0。
+const fn flag_of(name: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < FLAGS.len() {
+        let (flag, bit) = FLAGS[i];
+        if eq_ignore_case(flag.as_bytes(), name) {
+            return 1 << bit;
+        }
+        i += 1;
+    }
+    0
+}
+
+/// 编译期忽略大小写比较。
+const fn eq_ignore_case(a: &[u8], b: &[u8]) -> bool {
+    match (a, b) {
+        ([x, a @ ..], [y, b @ ..]) => x.eq_ignore_ascii_case(y) && eq_ignore_case(a, b),
+        ([], []) => true,
+        _ => false,
+    }
+}
+
+mod assertions {
+    use super::*;
+    use crate::{MmuMeta, VmMeta};
+    use static_assertions::const_assert_eq;
+
+    const_assert_eq!(X86_64::V_ADDR_BITS, 48);
+    const_assert_eq!(X86_64::MAX_LEVEL, 3);
+    const_assert_eq!(X86_64::PAGE_BITS, 12);
+    const_assert_eq!(X86_64::PPN_MASK, 0x000f_ffff_ffff_f000);
+
+    const_assert_eq!(VmFlags::<X86_64>::build_from_str("P|RW|US").val(), 0b111);
+    const_assert_eq!(
+        VmFlags::<X86_64>::build_from_str("p ps nx").val(),
+        1 << 63 | 1 << 7 | 1
+    );
+    const_assert_eq!(VmFlags::<X86_64>::build_from_str("PWT_PCD").val(), 0b11000);
+}
+
+#[test]
+fn test_from_str() {
+    assert_eq!(
+        "P | RW | NX".parse::<VmFlags<X86_64>>().map(VmFlags::val),
+        Ok(1 << 63 | 0b11)
+    );
+    // 不认识的名字被忽略
+    assert_eq!("P | XD".parse::<VmFlags<X86_64>>().map(VmFlags::val), Ok(1));
+}
+
+#[test]
+fn test_leaf_flags() {
+    use crate::MmuMeta;
+
+    // 0 级页表项的 PAT 位
+    assert_eq!(X86_64::leaf_flags(0xa3, 0), 0xa3);
+    assert_eq!(X86_64::leaf_flags(0x23, 0), 0x23);
+    assert_eq!(X86_64::leaf_flags(0x23, 1), 0xa3);
+}
+
+#[test]
+fn test_split() {
+    use crate::{
+        test_meta::{root_table, Frames},
+        IdentityMapper, PageTableMut, PPN, VPN,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<X86_64> = unsafe { root_table(&mut frames) };
+    let flags = VmFlags::<X86_64>::build_from_str("P|RW");
+    pt.map(
+        VPN::new(0x200)..VPN::new(0x400),
+        PPN::new(0x8000),
+        flags,
+        1,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    pt.split(VPN::new(0x200), 1, &mut frames, &IdentityMapper)
+        .unwrap();
+    // 拆分出的 0 级页表项不能带上 PS 位，否则会被当作 PAT 位
+    assert!(pt
+        .mappings(&IdentityMapper)
+        .all(|m| m.level == 0 && m.flags == flags));
+    assert_eq!(pt.mappings(&IdentityMapper).count(), 512);
+}
+
+#[test]
+fn test_regions() {
+    use crate::{
+        test_meta::{root_table, Frames},
+        IdentityMapper, PageTableMut, Region, PPN, VPN,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<X86_64> = unsafe { root_table(&mut frames) };
+    let flags = VmFlags::<X86_64>::build_from_str("P|RW");
+    // 4 KiB 页与其后的 2 MiB 页物理地址连续，只有 PS 位不同
+    for (start, end, level) in [(0x1ff, 0x200, 0), (0x200, 0x400, 1)] {
+        pt.map(
+            VPN::new(start)..VPN::new(end),
+            PPN::new(0x8000 + start),
+            flags,
+            level,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    }
+    assert!(pt.regions(&IdentityMapper).eq([Region {
+        vpn_range: VPN::new(0x1ff)..VPN::new(0x400),
+        ppn_start: PPN::new(0x81ff),
+        flags,
+    }]));
+}
+
+#[test]
+fn test_pat() {
+    use crate::{
+        test_meta::{root_table, Frames},
+        IdentityMapper, PAddr, PageTableMut, VAddr, PPN, VPN,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<X86_64> = unsafe { root_table(&mut frames) };
+    let flags = VmFlags::<X86_64>::build_from_str("P|RW");
+    let page_pat = unsafe { VmFlags::<X86_64>::from_raw(flags.val() | 1 << 7) };
+    let huge_pat = unsafe { VmFlags::<X86_64>::from_raw(flags.val() | HUGE_PAT) };
+    for (start, end, flags, level) in [
+        (0x1ff, 0x200, page_pat, 0),
+        (0x200, 0x400, flags, 1),
+        (0x400, 0x600, huge_pat, 1),
+    ] {
+        pt.map(
+            VPN::new(start)..VPN::new(end),
+            PPN::new(0x8000 + start),
+            flags,
+            level,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    }
+    // 大页的 PAT 位不属于物理页号
+    assert_eq!(
+        pt.translate(VAddr::new(0x40_1234), &IdentityMapper),
+        Some((
+            PAddr::new(0x840_1234),
+            unsafe { VmFlags::from_raw(0x1083) },
+            1
+        ))
+    );
+    // 0 级页表项的 PAT 位与大页的 PS 位是同一位，不能合并
+    assert_eq!(pt.regions(&IdentityMapper).count(), 3);
+}
@@ -0,0 +1,47 @@
+﻿use core::fmt;
+
+/// 页表操作失败的原因。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PageTableError {
+    /// 目标虚页已经被映射。
+    AlreadyMapped,
+    /// 目标虚页没有被映射。
+    NotMapped,
+    /// 无法分配新的页表。
+    OutOfFrames,
+    /// 虚页号或物理页号没有按目标级别对齐。
+    Misaligned,
+    /// 目标虚页位于一个已经存在的大页中。
+    HugePageConflict,
+    /// 目标级别超过页表级别，或者方案不允许这一级的大页。
+    InvalidLevel,
+    /// 虚页号不在页表范围内。
+    AddressOutOfRange,
+    /// 页表项属性不是有效的叶子页表项属性。
+    InvalidFlags,
+    /// 目标虚页位于共享的页表项中。
+    SharedTable,
+    /// 共享页表模板或反向映射索引的容量已满。
+    RegistryFull,
+    /// 方案或物理页访问方式不支持这个操作。
+    Unsupported,
+}
+
+impl fmt::Display for PageTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::AlreadyMapped => "page already mapped",
+            Self::NotMapped => "page not mapped",
+            Self::OutOfFrames => "out of frames",
+            Self::Misaligned => "misaligned page number",
+            Self::HugePageConflict => "conflict with a huge page",
+            Self::InvalidLevel => "invalid level",
+            Self::AddressOutOfRange => "address out of range",
+            Self::InvalidFlags => "invalid flags",
+            Self::SharedTable => "conflict with a shared entry",
+            Self::RegistryFull => "registry full",
+            Self::Unsupported => "unsupported operation",
+        };
+        f.write_str(msg)
+    }
+}
@@ -1,4 +1,4 @@
-﻿use crate::{Pte, VmMeta, PPN};
+﻿use crate::{Pte, RawPte, VmMeta, PPN};
 use core::{
     marker::PhantomData,
     ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign},
@@ -9,11 +9,11 @@ use core::{
 /// 页表项属性一定完全包含在页表项中，所以独立的页表项属性实现为一个无法获取地址的页表项。
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 #[repr(transparent)]
-pub struct VmFlags<Meta: VmMeta>(usize, PhantomData<Meta>);
+pub struct VmFlags<Meta: VmMeta>(Meta::Raw, PhantomData<Meta>);
 
 impl<Meta: VmMeta> VmFlags<Meta> {
     /// 空页表项属性。
-    pub const ZERO: Self = Self(0, PhantomData);
+    pub const ZERO: Self = Self(Meta::Raw::ZERO, PhantomData);
 
     /// 表示页表项有效的属性。
     pub const VALID: Self = Self(Meta::VALID_FLAG, PhantomData);
@@ -24,20 +24,32 @@ impl<Meta: VmMeta> VmFlags<Meta> {
     ///
     /// 调用者需要保证 `raw` 里不表示属性的位全是零。
     #[inline]
-    pub const unsafe fn from_raw(raw: usize) -> Self {
+    pub const unsafe fn from_raw(raw: Meta::Raw) -> Self {
         Self(raw, PhantomData)
     }
 
     /// 取出值。
     #[inline]
-    pub const fn val(self) -> usize {
+    pub const fn val(self) -> Meta::Raw {
         self.0
     }
 
     /// 判断是否包含所有指定的位。
+    ///
+    /// 泛型的位运算不能在编译期调用，所以逐字节比较。
     #[inline]
     pub const fn contains(self, flags: VmFlags<Meta>) -> bool {
-        self.0 & flags.0 == flags.0
+        let a = &self.0 as *const Meta::Raw as *const u8;
+        let b = &flags.0 as *const Meta::Raw as *const u8;
+        let mut i = 0;
+        while i < core::mem::size_of::<Meta::Raw>() {
+            let (a, b) = unsafe { (*a.add(i), *b.add(i)) };
+            if a & b != b {
+                return false;
+            }
+            i += 1;
+        }
+        true
     }
 
     /// 如果页表项指向一个页而非子页表，返回 `true`。
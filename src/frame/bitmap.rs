@@ -0,0 +1,109 @@
+﻿use super::{align_up, FrameAllocator};
+use crate::{VmMeta, PPN};
+use core::ops::Range;
+
+const BITS: usize = usize::BITS as usize;
+
+/// 位图分配器。
+///
+/// 以 `N` 个 `usize` 作为位图，最多管理 `N * usize::BITS` 个物理页帧，分配时首次适配。
+pub struct BitmapAllocator<Meta: VmMeta, const N: usize> {
+    base: PPN<Meta>,
+    free: [usize; N],
+}
+
+impl<Meta: VmMeta, const N: usize> BitmapAllocator<Meta, N> {
+    /// 管理 `range` 范围的物理页。
+    ///
+    /// # Panics
+    ///
+    /// `range` 中的物理页数量超过位图容量。
+    pub const fn new(range: Range<PPN<Meta>>) -> Self {
+        let len = range.end.val().saturating_sub(range.start.val());
+        assert!(len <= (N * BITS) as u64, "range is too long for the bitmap");
+        let len = len as usize;
+        let mut free = [0; N];
+        let mut i = 0;
+        while i < len / BITS {
+            free[i] = usize::MAX;
+            i += 1;
+        }
+        let rest = len % BITS;
+        if rest != 0 {
+            free[i] = (1 << rest) - 1;
+        }
+        Self {
+            base: range.start,
+            free,
+        }
+    }
+
+    /// 空闲的物理页帧数量。
+    #[inline]
+    pub fn available(&self) -> usize {
+        self.free
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    #[inline]
+    fn is_free(&self, i: usize) -> bool {
+        (self.free[i / BITS] >> (i % BITS)) & 1 == 1
+    }
+}
+
+impl<Meta: VmMeta, const N: usize> FrameAllocator<Meta> for BitmapAllocator<Meta, N> {
+    fn allocate(&mut self, count: usize, align: usize) -> Option<PPN<Meta>> {
+        if count == 0 {
+            return None;
+        }
+        let base = self.base.val();
+        let align = align as u64;
+        let mut i = (align_up(base, align) - base) as usize;
+        while i + count <= N * BITS {
+            // 找到范围内最后一个已分配的页帧，从它之后重新对齐
+            match (i..i + count).rev().find(|j| !self.is_free(*j)) {
+                Some(j) => i = (align_up(base + j as u64 + 1, align) - base) as usize,
+                None => {
+                    for j in i..i + count {
+                        self.free[j / BITS] &= !(1 << (j % BITS));
+                    }
+                    return Some(PPN::new(base + i as u64));
+                }
+            }
+        }
+        None
+    }
+
+    fn deallocate(&mut self, ppn: PPN<Meta>, count: usize) {
+        let start = (ppn.val() - self.base.val()) as usize;
+        for i in start..start + count {
+            debug_assert!(
+                !self.is_free(i),
+                "double free of {:?}",
+                ppn + (i - start) as u64
+            );
+            self.free[i / BITS] |= 1 << (i % BITS);
+        }
+    }
+}
+
+#[test]
+fn test_bitmap() {
+    use crate::test_meta::Sv39;
+
+    let mut bitmap = BitmapAllocator::<Sv39, 2>::new(PPN::new(0x1003)..PPN::new(0x1083));
+    assert_eq!(bitmap.available(), 0x80);
+    assert_eq!(bitmap.allocate_one(), Some(PPN::new(0x1003)));
+    assert_eq!(bitmap.allocate(4, 4), Some(PPN::new(0x1004)));
+    assert_eq!(bitmap.allocate(3, 1), Some(PPN::new(0x1008)));
+    assert_eq!(bitmap.allocate(0x40, 0x40), Some(PPN::new(0x1040)));
+    assert_eq!(bitmap.allocate(0x40, 0x40), None);
+    assert_eq!(bitmap.available(), 0x80 - 0x48);
+
+    bitmap.deallocate(PPN::new(0x1004), 4);
+    assert_eq!(bitmap.allocate(5, 1), Some(PPN::new(0x100b)));
+    assert_eq!(bitmap.allocate(4, 2), Some(PPN::new(0x1004)));
+    assert_eq!(bitmap.allocate_one(), Some(PPN::new(0x1010)));
+}
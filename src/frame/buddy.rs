@@ -0,0 +1,170 @@
+﻿use super::FrameAllocator;
+use crate::{VmMeta, PPN};
+use core::ops::Range;
+
+const BITS: usize = usize::BITS as usize;
+
+/// 伙伴分配器。
+///
+/// 管理 `ORDER` 个阶的空闲块，第 `k` 阶的块包含 `2^k` 个物理页帧，并按 `2^k` 个物理页对齐，
+/// 因此可以分配对齐到大页的连续物理页。
+///
+/// 各阶的空闲块以位图记录，共用 `N` 个 `usize`，最多管理 `N * usize::BITS / 2` 个物理页帧。
+pub struct BuddyAllocator<Meta: VmMeta, const N: usize, const ORDER: usize> {
+    base: PPN<Meta>,
+    free: [usize; N],
+}
+
+impl<Meta: VmMeta, const N: usize, const ORDER: usize> BuddyAllocator<Meta, N, ORDER> {
+    /// 最多管理的物理页帧数量。
+    const CAPACITY: usize = N * BITS / 2;
+
+    /// 管理 `range` 范围的物理页。
+    ///
+    /// # Panics
+    ///
+    /// 从 `range.start` 按最高阶对齐后，物理页数量超过位图容量。
+    pub fn new(range: Range<PPN<Meta>>) -> Self {
+        assert!(ORDER > 0 && Self::CAPACITY >> (ORDER - 1) > 0);
+        let base = range.start.val() & !((1 << (ORDER - 1)) - 1);
+        assert!(
+            range.end.val().saturating_sub(base) <= Self::CAPACITY as u64,
+            "range is too long for the bitmap"
+        );
+        let mut ans = Self {
+            base: PPN::new(base),
+            free: [0; N],
+        };
+        ans.free_range(
+            (range.start.val() - base) as usize,
+            range.end.val().saturating_sub(base) as usize,
+        );
+        ans
+    }
+
+    /// 空闲的物理页帧数量。
+    pub fn available(&self) -> usize {
+        (0..ORDER)
+            .map(|k| {
+                let (offset, len) = Self::bitmap(k);
+                (offset..offset + len).filter(|i| self.get(*i)).count() << k
+            })
+            .sum()
+    }
+
+    /// 第 `k` 阶位图的起始位置和长度。
+    #[inline]
+    fn bitmap(k: usize) -> (usize, usize) {
+        let offset = (0..k).map(|j| Self::CAPACITY >> j).sum();
+        (offset, Self::CAPACITY >> k)
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (self.free[i / BITS] >> (i % BITS)) & 1 == 1
+    }
+
+    #[inline]
+    fn set(&mut self, i: usize, value: bool) {
+        if value {
+            self.free[i / BITS] |= 1 << (i % BITS);
+        } else {
+            self.free[i / BITS] &= !(1 << (i % BITS));
+        }
+    }
+
+    /// 找到一个第 `k` 阶的空闲块。
+    fn find(&self, k: usize) -> Option<usize> {
+        let (offset, len) = Self::bitmap(k);
+        let mut i = offset;
+        while i < offset + len {
+            // 跳过全零的字
+            if i % BITS == 0 && self.free[i / BITS] == 0 {
+                i += BITS;
+            } else if self.get(i) {
+                return Some(i - offset);
+            } else {
+                i += 1;
+            }
+        }
+        None
+    }
+
+    /// 回收第 `k` 阶的第 `index` 块，并尽量与伙伴合并。
+    fn free_block(&mut self, mut index: usize, mut k: usize) {
+        while k + 1 < ORDER {
+            let buddy = Self::bitmap(k).0 + (index ^ 1);
+            if !self.get(buddy) {
+                break;
+            }
+            self.set(buddy, false);
+            index >>= 1;
+            k += 1;
+        }
+        self.set(Self::bitmap(k).0 + index, true);
+    }
+
+    /// 回收相对于 `base` 的 `start..end` 范围，拆分为尽量大的对齐块。
+    fn free_range(&mut self, mut start: usize, end: usize) {
+        while start < end {
+            let mut k = (start.trailing_zeros() as usize).min(ORDER - 1);
+            while start + (1 << k) > end {
+                k -= 1;
+            }
+            self.free_block(start >> k, k);
+            start += 1 << k;
+        }
+    }
+}
+
+impl<Meta: VmMeta, const N: usize, const ORDER: usize> FrameAllocator<Meta>
+    for BuddyAllocator<Meta, N, ORDER>
+{
+    fn allocate(&mut self, count: usize, align: usize) -> Option<PPN<Meta>> {
+        if count == 0 {
+            return None;
+        }
+        let order = (count.next_power_of_two().max(align).trailing_zeros()) as usize;
+        let (index, mut k) = (order..ORDER).find_map(|k| self.find(k).map(|i| (i, k)))?;
+        self.set(Self::bitmap(k).0 + index, false);
+        // 拆分大块，后一半放回低一阶
+        let start = index << k;
+        while k > order {
+            k -= 1;
+            self.set(Self::bitmap(k).0 + (start >> k) + 1, true);
+        }
+        // 归还多余的页帧
+        self.free_range(start + count, start + (1 << order));
+        Some(self.base + start as u64)
+    }
+
+    #[inline]
+    fn deallocate(&mut self, ppn: PPN<Meta>, count: usize) {
+        let start = (ppn.val() - self.base.val()) as usize;
+        self.free_range(start, start + count);
+    }
+}
+
+#[test]
+fn test_buddy() {
+    use crate::test_meta::Sv39;
+
+    // 2 个 512 页的块，加上前后的零散页
+    let mut buddy = BuddyAllocator::<Sv39, 64, 10>::new(PPN::new(0x1ff)..PPN::new(0x601));
+    assert_eq!(buddy.available(), 0x402);
+    assert_eq!(buddy.allocate(0x200, 0x200), Some(PPN::new(0x200)));
+    assert_eq!(buddy.allocate(0x100, 0x200), Some(PPN::new(0x400)));
+    assert_eq!(buddy.allocate(0x200, 0x200), None);
+    assert_eq!(buddy.available(), 0x102);
+    assert_eq!(buddy.allocate(3, 1), Some(PPN::new(0x500)));
+    assert_eq!(buddy.allocate_one(), Some(PPN::new(0x1ff)));
+    assert_eq!(buddy.allocate_one(), Some(PPN::new(0x503)));
+
+    // 回收后合并
+    buddy.deallocate(PPN::new(0x200), 0x200);
+    buddy.deallocate(PPN::new(0x400), 0x100);
+    buddy.deallocate(PPN::new(0x500), 4);
+    assert_eq!(buddy.available(), 0x401);
+    assert_eq!(buddy.allocate(0x200, 1), Some(PPN::new(0x200)));
+    assert_eq!(buddy.allocate(0x200, 1), Some(PPN::new(0x400)));
+}
@@ -0,0 +1,57 @@
+﻿use super::{align_up, FrameAllocator};
+use crate::{VmMeta, PPN};
+use core::ops::Range;
+
+/// 线性分配器。
+///
+/// 按顺序分配一段物理页，不回收。适用于启动早期。
+pub struct BumpAllocator<Meta: VmMeta> {
+    next: PPN<Meta>,
+    end: PPN<Meta>,
+}
+
+impl<Meta: VmMeta> BumpAllocator<Meta> {
+    /// 从 `range` 范围的物理页中分配。
+    #[inline]
+    pub const fn new(range: Range<PPN<Meta>>) -> Self {
+        Self {
+            next: range.start,
+            end: range.end,
+        }
+    }
+
+    /// 尚未分配的物理页范围。
+    #[inline]
+    pub fn remaining(&self) -> Range<PPN<Meta>> {
+        self.next..self.end
+    }
+}
+
+impl<Meta: VmMeta> FrameAllocator<Meta> for BumpAllocator<Meta> {
+    fn allocate(&mut self, count: usize, align: usize) -> Option<PPN<Meta>> {
+        let start = align_up(self.next.val(), align as u64);
+        let end = start.checked_add(count as u64)?;
+        if end <= self.end.val() {
+            self.next = PPN::new(end);
+            Some(PPN::new(start))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn deallocate(&mut self, _ppn: PPN<Meta>, _count: usize) {}
+}
+
+#[test]
+fn test_bump() {
+    use crate::test_meta::Sv39;
+
+    let mut bump = BumpAllocator::<Sv39>::new(PPN::new(0x801)..PPN::new(0xa00));
+    assert_eq!(bump.allocate_one(), Some(PPN::new(0x801)));
+    assert_eq!(bump.allocate(2, 4), Some(PPN::new(0x804)));
+    assert_eq!(bump.allocate(1, 0x200), None);
+    assert_eq!(bump.allocate(0x100, 0x100), Some(PPN::new(0x900)));
+    assert_eq!(bump.remaining(), PPN::new(0xa00)..PPN::new(0xa00));
+    assert_eq!(bump.allocate_one(), None);
+}
@@ -0,0 +1,52 @@
+﻿mod bitmap;
+mod buddy;
+mod bump;
+
+use crate::{VmMeta, PPN};
+
+pub use bitmap::BitmapAllocator;
+pub use buddy::BuddyAllocator;
+pub use bump::BumpAllocator;
+
+/// 物理页帧分配器。
+///
+/// 扩展页表的操作通过它获得新的页表页帧，并通过 [`PhysMapper`](crate::PhysMapper) 清零。
+pub trait FrameAllocator<Meta: VmMeta> {
+    /// 分配 `count` 个连续的物理页帧，起始物理页号是 `align` 的整数倍。
+    ///
+    /// `align` 必须是 2 的幂。物理页帧耗尽时返回 `None`。
+    fn allocate(&mut self, count: usize, align: usize) -> Option<PPN<Meta>>;
+
+    /// 回收从 `ppn` 开始的 `count` 个连续的物理页帧。
+    fn deallocate(&mut self, ppn: PPN<Meta>, count: usize);
+
+    /// 分配一个物理页帧。
+    #[inline]
+    fn allocate_one(&mut self) -> Option<PPN<Meta>> {
+        self.allocate(1, 1)
+    }
+
+    /// 回收一个物理页帧。
+    #[inline]
+    fn deallocate_one(&mut self, ppn: PPN<Meta>) {
+        self.deallocate(ppn, 1)
+    }
+}
+
+impl<Meta: VmMeta, T: FrameAllocator<Meta>> FrameAllocator<Meta> for &mut T {
+    #[inline]
+    fn allocate(&mut self, count: usize, align: usize) -> Option<PPN<Meta>> {
+        (**self).allocate(count, align)
+    }
+
+    #[inline]
+    fn deallocate(&mut self, ppn: PPN<Meta>, count: usize) {
+        (**self).deallocate(ppn, count)
+    }
+}
+
+/// 将 `n` 向上对齐到 `align` 的整数倍。
+#[inline]
+const fn align_up(n: u64, align: u64) -> u64 {
+    (n + align - 1) & !(align - 1)
+}
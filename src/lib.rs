@@ -4,33 +4,52 @@
 #![deny(warnings, unstable_features, missing_docs)]
 
 mod addr;
+mod error;
 mod flags;
+mod frame;
+mod mapper;
 mod pte;
+mod raw;
+mod rmap;
+mod space;
 mod table;
+mod template;
+
+#[path = "arch/riscv.rs"]
+pub mod riscv;
+
+#[path = "arch/arm.rs"]
+pub mod arm;
+#[path = "arch/x86.rs"]
+pub mod x86;
 
 cfg_if::cfg_if! {
     if #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))] {
-        #[path = "arch/riscv.rs"]
-        mod arch;
+        pub use riscv::*;
     } else if #[cfg(target_arch = "aarch64")] {
-        #[path = "arch/arm.rs"]
-        mod arch;
+        pub use arm::*;
     } else if #[cfg(target_arch = "x86_64")] {
-        #[path = "arch/x86.rs"]
-        mod arch;
-    } else {
-        compile_error!("Unsupported architecture");
+        pub use x86::*;
     }
 }
 
 pub use addr::*;
-pub use arch::*;
+pub use error::PageTableError;
 pub use flags::VmFlags;
+pub use frame::{BitmapAllocator, BuddyAllocator, BumpAllocator, FrameAllocator};
+pub use mapper::{IdentityMapper, LinearMapper, PhysMapper, RecursiveMapper};
 pub use pte::Pte;
+pub use raw::RawPte;
+pub use rmap::{reverse_lookup, RmapIndex};
+pub use space::{AddressSpace, ReleaseLeaf};
 pub use table::*;
+pub use template::SharedTemplate;
 
 /// 地址转换单元元数据。
 pub trait MmuMeta {
+    /// 页表项的存储类型。
+    type Raw: RawPte;
+
     /// 物理地址位数，用于计算物理页号形式。
     const P_ADDR_BITS: usize;
 
@@ -46,11 +65,16 @@ pub trait MmuMeta {
     /// 表示页表项有效的标志位。
     ///
     /// 一般就是最低位。
-    const VALID_FLAG: usize = 1;
+    const VALID_FLAG: Self::Raw = <Self::Raw as RawPte>::ONE;
+
+    /// 指向子页表的页表项的特性位。
+    ///
+    /// 新建中间页表时使用，默认只有有效位。
+    const TABLE_FLAGS: Self::Raw = Self::VALID_FLAG;
 
     /// 判断页表项是否有效。
     #[inline]
-    fn is_valid(flags: usize) -> bool {
+    fn is_valid(flags: Self::Raw) -> bool {
         flags & Self::VALID_FLAG == Self::VALID_FLAG
     }
 
@@ -59,13 +83,81 @@ pub trait MmuMeta {
     /// # NOTE
     ///
     /// 为了分散开销，这个方法的实现不会判断页表项是否有效。
-    fn is_leaf(flags: usize) -> bool;
+    fn is_leaf(flags: Self::Raw) -> bool;
+
+    /// 将叶子页表项的特性位调整为 `level` 级叶子页表项的形式。
+    ///
+    /// 用于页表项的形式随级别变化的方案，例如用特性位区分大页的方案。默认不作修改。
+    #[inline]
+    fn leaf_flags(flags: Self::Raw, _level: usize) -> Self::Raw {
+        flags
+    }
+
+    /// `level` 级叶子页表项中占用物理页号字段低位的特性位。
+    ///
+    /// 用于在大页页表项的物理页号低位放置特性位的方案，例如 x86 大页的 PAT 位。默认没有。
+    #[inline]
+    fn leaf_ppn_flags(_level: usize) -> Self::Raw {
+        <Self::Raw as RawPte>::ZERO
+    }
+
+    /// 标记写时复制页的软件保留位。
+    ///
+    /// 为零表示方案不支持写时复制，这是默认值。
+    const COW_FLAG: Self::Raw = <Self::Raw as RawPte>::ZERO;
+
+    /// 标记共享页表项的软件保留位。
+    ///
+    /// 共享页表项指向属于其他页表的子页表或物理页，见 [`PageTableMut::share`]。
+    /// 这一位在指向子页表的页表项中也必须被硬件忽略。为零表示方案不支持共享，这是默认值。
+    const SHARED_FLAG: Self::Raw = <Self::Raw as RawPte>::ZERO;
+
+    /// 硬件访问页时设置的访问位。
+    ///
+    /// 用于回收页时判断页的冷热，见 [`PageTableMut::harvest_accessed`]。为零表示方案不支持，这是默认值。
+    const ACCESSED_FLAG: Self::Raw = <Self::Raw as RawPte>::ZERO;
+
+    /// 判断叶子页表项是否可写。
+    ///
+    /// 支持写时复制的方案需要实现这个方法，默认总是不可写。
+    #[inline]
+    fn is_writable(_flags: Self::Raw) -> bool {
+        false
+    }
+
+    /// 设置叶子页表项是否可写。
+    ///
+    /// 支持写时复制的方案需要实现这个方法，默认不作修改。
+    #[inline]
+    fn set_writable(flags: Self::Raw, _writable: bool) -> Self::Raw {
+        flags
+    }
+
+    /// 如果 `level` 级页表项可以是叶子，返回 `true`。
+    ///
+    /// 用于只允许部分级别的大页的方案。0 级页表项总是可以是叶子，默认所有级别都可以。
+    #[inline]
+    fn allow_huge(_level: usize) -> bool {
+        true
+    }
 
     /// 格式化特性位。
     #[inline]
-    fn fmt_flags(f: &mut core::fmt::Formatter, flags: usize) -> core::fmt::Result {
+    fn fmt_flags(f: &mut core::fmt::Formatter, flags: Self::Raw) -> core::fmt::Result {
         write!(f, "{flags:018x}")
     }
+
+    /// 格式化 `level` 级叶子页表项的特性位。
+    ///
+    /// 用于特性位的含义随级别变化的方案。默认与 [`fmt_flags`](Self::fmt_flags) 相同。
+    #[inline]
+    fn fmt_leaf_flags(
+        f: &mut core::fmt::Formatter,
+        flags: Self::Raw,
+        _level: usize,
+    ) -> core::fmt::Result {
+        Self::fmt_flags(f, flags)
+    }
 }
 
 /// 页式虚存元数据。
@@ -77,23 +169,31 @@ pub trait VmMeta: 'static + MmuMeta + Copy + Ord + core::hash::Hash + core::fmt:
     const MAX_LEVEL: usize = Self::LEVEL_BITS.len() - 1;
 
     /// 页表项中的物理页号掩码。
-    const PPN_MASK: usize = ppn_mask::<Self>();
+    ///
+    /// 页表项不超过 64 位，所以总是用 `u64` 表示。
+    const PPN_MASK: u64 = ppn_mask::<Self>();
 
     /// `level` 级页表容纳的总页数。
     #[inline]
-    fn pages_in_table(level: usize) -> usize {
+    fn pages_in_table(level: usize) -> u64 {
         1 << Self::LEVEL_BITS[..=level].iter().sum::<usize>()
     }
 
     /// `level` 级页表容纳的总字节数。
     #[inline]
-    fn bytes_in_table(level: usize) -> usize {
+    fn bytes_in_table(level: usize) -> u64 {
         1 << (Self::LEVEL_BITS[..=level].iter().sum::<usize>() + Self::PAGE_BITS)
     }
 
+    /// `level` 级页容纳的总页数。
+    #[inline]
+    fn pages_in_page(level: usize) -> u64 {
+        1 << Self::LEVEL_BITS[..level].iter().sum::<usize>()
+    }
+
     /// `level` 级页容纳的总字节数。
     #[inline]
-    fn bytes_in_page(level: usize) -> usize {
+    fn bytes_in_page(level: usize) -> u64 {
         1 << (Self::LEVEL_BITS[..level].iter().sum::<usize>() + Self::PAGE_BITS)
     }
 
@@ -103,26 +203,27 @@ pub trait VmMeta: 'static + MmuMeta + Copy + Ord + core::hash::Hash + core::fmt:
     ///
     /// 为了零开销抽象，这个方法的实现可能不会判断 PTE 是否 valid。
     #[inline]
-    fn is_huge(value: usize, level: usize) -> bool {
+    fn is_huge(value: Self::Raw, level: usize) -> bool {
         level != 0 && Self::is_leaf(value)
     }
 
     /// 从 PTE 中获得 PPN。
     #[inline]
-    fn ppn(value: usize) -> PPN<Self> {
-        PPN::new((value & Self::PPN_MASK) >> Self::PPN_POS)
+    fn ppn(value: Self::Raw) -> PPN<Self> {
+        PPN::new(((value & Self::Raw::from_u64(Self::PPN_MASK)) >> Self::PPN_POS).to_u64())
     }
 
     /// 设置页表项的 ppn。
     #[inline]
-    fn set_ppn(value: &mut usize, ppn: PPN<Self>) {
-        *value |= (ppn.val() << Self::PPN_POS) & Self::PPN_MASK;
+    fn set_ppn(value: &mut Self::Raw, ppn: PPN<Self>) {
+        *value |=
+            (Self::Raw::from_u64(ppn.val()) << Self::PPN_POS) & Self::Raw::from_u64(Self::PPN_MASK);
     }
 
     /// 清除页表项中的 ppn。
     #[inline]
-    fn clear_ppn(value: &mut usize) {
-        *value &= !Self::PPN_MASK;
+    fn clear_ppn(value: &mut Self::Raw) {
+        *value &= !Self::Raw::from_u64(Self::PPN_MASK);
     }
 }
 
@@ -130,16 +231,22 @@ pub trait VmMeta: 'static + MmuMeta + Copy + Ord + core::hash::Hash + core::fmt:
 impl<T: 'static + MmuMeta + Copy + Ord + core::hash::Hash + core::fmt::Debug> VmMeta for T {}
 
 /// 生成一个 `bits` 位的掩码。
+///
+/// 超过 `u64` 位数的部分被忽略。
 #[inline]
-const fn mask(bits: usize) -> usize {
-    (1 << bits) - 1
+const fn mask(bits: usize) -> u64 {
+    if bits < u64::BITS as usize {
+        (1 << bits) - 1
+    } else {
+        u64::MAX
+    }
 }
 
 /// 计算 pte 中 ppn 的掩码。
 #[inline]
-const fn ppn_mask<Meta: MmuMeta>() -> usize {
-    let m0: usize = !mask(Meta::PPN_POS);
-    let m1: usize = mask(Meta::PPN_POS + Meta::P_ADDR_BITS - Meta::PAGE_BITS);
+const fn ppn_mask<Meta: MmuMeta>() -> u64 {
+    let m0: u64 = !((1 << Meta::PPN_POS) - 1);
+    let m1: u64 = (1 << (Meta::PPN_POS + Meta::P_ADDR_BITS - Meta::PAGE_BITS)) - 1;
     m0 & m1
 }
 
@@ -158,16 +265,122 @@ mod test_meta {
     pub(crate) struct Sv39;
 
     impl super::MmuMeta for Sv39 {
+        type Raw = u64;
         const P_ADDR_BITS: usize = 56;
         const PAGE_BITS: usize = 12;
         const LEVEL_BITS: &'static [usize] = &[9; 3];
         const PPN_POS: usize = 10;
+        const COW_FLAG: u64 = 1 << 8;
+        const SHARED_FLAG: u64 = 1 << 9;
+        const ACCESSED_FLAG: u64 = 1 << 6;
 
         #[inline]
-        fn is_leaf(value: usize) -> bool {
-            const MASK: usize = 0b1110;
+        fn is_leaf(value: u64) -> bool {
+            const MASK: u64 = 0b1110;
             value & MASK != 0
         }
+
+        #[inline]
+        fn is_writable(flags: u64) -> bool {
+            flags & 0b100 != 0
+        }
+
+        #[inline]
+        fn set_writable(flags: u64, writable: bool) -> u64 {
+            if writable {
+                flags | 0b100
+            } else {
+                flags & !0b100
+            }
+        }
+    }
+
+    /// 测试用的物理页。
+    #[repr(C, align(4096))]
+    pub(crate) struct Page([u8; 4096]);
+
+    /// 测试用的页帧分配器。
+    ///
+    /// 页帧在当前地址空间中恒等映射，分配出的页帧不能移动。
+    pub(crate) struct Frames<const N: usize> {
+        pages: [Page; N],
+        used: [bool; N],
+    }
+
+    impl<const N: usize> Frames<N> {
+        pub fn new() -> Self {
+            Self {
+                pages: core::array::from_fn(|_| Page([0; 4096])),
+                used: [false; N],
+            }
+        }
+
+        /// 已分配的页帧数。
+        pub fn used(&self) -> usize {
+            self.used.iter().filter(|used| **used).count()
+        }
+    }
+
+    /// 访问页帧。
+    pub(crate) fn frame_ptr<Meta: super::VmMeta>(
+        ppn: super::PPN<Meta>,
+    ) -> core::ptr::NonNull<super::Pte<Meta>> {
+        use super::PhysMapper;
+        super::IdentityMapper.map_frame(ppn).unwrap().cast()
+    }
+
+    /// 从 `frames` 分配一个根页表。
+    ///
+    /// # Safety
+    ///
+    /// 返回的页表不能在 `frames` 销毁后使用。
+    pub(crate) unsafe fn root_table<'a, Meta: super::VmMeta, const N: usize>(
+        frames: &mut Frames<N>,
+    ) -> super::PageTableMut<'a, Meta> {
+        let root = super::FrameAllocator::<Meta>::allocate_one(frames).unwrap();
+        super::PageTableMut::from_root(frame_ptr(root))
+    }
+
+    /// 在 `pt` 中依次建立 `maps` 中的映射，中间页表从 `frames` 分配。
+    ///
+    /// 每项依次是虚页号范围、起始物理页号、属性和级别。
+    pub(crate) fn map_pages<Meta: super::VmMeta, const N: usize>(
+        pt: &mut super::PageTableMut<Meta>,
+        frames: &mut Frames<N>,
+        maps: &[(core::ops::Range<u64>, u64, super::VmFlags<Meta>, usize)],
+    ) {
+        for (range, ppn, flags, level) in maps {
+            pt.map(
+                super::VPN::new(range.start)..super::VPN::new(range.end),
+                super::PPN::new(*ppn),
+                *flags,
+                *level,
+                frames,
+                &super::IdentityMapper,
+            )
+            .unwrap();
+        }
+    }
+
+    /// 用于各种 4 KiB 页的方案。
+    impl<Meta: super::VmMeta, const N: usize> super::FrameAllocator<Meta> for Frames<N> {
+        fn allocate(&mut self, count: usize, _align: usize) -> Option<super::PPN<Meta>> {
+            assert_eq!(count, 1);
+            let i = self.used.iter().position(|used| !*used)?;
+            self.used[i] = true;
+            Some(super::PPN::new(self.pages[i].0.as_ptr() as u64 >> 12))
+        }
+
+        fn deallocate(&mut self, ppn: super::PPN<Meta>, count: usize) {
+            assert_eq!(count, 1);
+            let i = self
+                .pages
+                .iter()
+                .position(|page| page.0.as_ptr() as u64 >> 12 == ppn.val())
+                .unwrap();
+            assert!(self.used[i]);
+            self.used[i] = false;
+        }
     }
 
     #[test]
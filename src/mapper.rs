@@ -0,0 +1,130 @@
+﻿use crate::{Pos, Pte, VAddr, VmMeta, PPN, VPN};
+use core::ptr::NonNull;
+
+/// 物理页在当前地址空间中的访问方式。
+///
+/// 遍历和修改页表时通过它访问子页表。
+pub trait PhysMapper<Meta: VmMeta> {
+    /// 获取物理页 `ppn` 在当前地址空间中的指针。
+    ///
+    /// 不能直接访问任意物理页的方案返回 `None`。
+    fn map_frame(&self, ppn: PPN<Meta>) -> Option<NonNull<u8>>;
+
+    /// 获取位于物理页 `ppn` 的子页表在当前地址空间中的指针。
+    ///
+    /// `pos.level` 是子页表的级别，`pos.vpn` 是子页表容纳的一个虚页。
+    /// 默认通过 [`map_frame`](Self::map_frame) 访问。
+    #[inline]
+    fn map_table(&self, ppn: PPN<Meta>, pos: Pos<Meta>) -> NonNull<Pte<Meta>> {
+        let _ = pos;
+        self.map_frame(ppn)
+            .expect("physical frame is not accessible")
+            .cast()
+    }
+}
+
+impl<Meta: VmMeta, T: PhysMapper<Meta>> PhysMapper<Meta> for &T {
+    #[inline]
+    fn map_frame(&self, ppn: PPN<Meta>) -> Option<NonNull<u8>> {
+        (**self).map_frame(ppn)
+    }
+
+    #[inline]
+    fn map_table(&self, ppn: PPN<Meta>, pos: Pos<Meta>) -> NonNull<Pte<Meta>> {
+        (**self).map_table(ppn, pos)
+    }
+}
+
+/// 恒等映射，物理地址就是虚地址。
+///
+/// 不能访问超出 `usize` 范围的物理页。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct IdentityMapper;
+
+impl<Meta: VmMeta> PhysMapper<Meta> for IdentityMapper {
+    #[inline]
+    fn map_frame(&self, ppn: PPN<Meta>) -> Option<NonNull<u8>> {
+        NonNull::new(usize::try_from(ppn.base().val()).ok()? as _)
+    }
+}
+
+/// 线性映射，物理地址加上固定的偏移就是虚地址。
+///
+/// 适用于高半部分的直接映射区域。不能访问超出 `usize` 范围的物理页。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LinearMapper {
+    /// 虚地址与物理地址之差。
+    pub offset: usize,
+}
+
+impl<Meta: VmMeta> PhysMapper<Meta> for LinearMapper {
+    #[inline]
+    fn map_frame(&self, ppn: PPN<Meta>) -> Option<NonNull<u8>> {
+        let paddr = usize::try_from(ppn.base().val()).ok()?;
+        NonNull::new(paddr.wrapping_add(self.offset) as _)
+    }
+}
+
+/// 递归映射，根页表的第 `index` 项指向根页表自身。
+///
+/// 只能访问已经链接到页表树上的页表，无法访问任意物理页。
+/// 要求各级页表的页表项数量相同。
+///
+/// # Panics
+///
+/// `index` 为 0 时，0 号虚页所在页表的虚地址是空指针，访问这个页表会 panic。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RecursiveMapper {
+    /// 根页表中指向自身的页表项序号。
+    pub index: usize,
+}
+
+impl RecursiveMapper {
+    /// 容纳 `vpn` 的 `level` 级页表在当前地址空间中的虚地址。
+    pub fn table_vaddr<Meta: VmMeta>(&self, vpn: VPN<Meta>, level: usize) -> VAddr<Meta> {
+        // 经过 level + 1 次递归项后，剩下的序号依次取自 vpn 在更高级页表中的序号
+        let mut val = 0;
+        for i in (0..=Meta::MAX_LEVEL).rev() {
+            let index = if i + level >= Meta::MAX_LEVEL {
+                self.index
+            } else {
+                vpn.index_in(i + level + 1)
+            };
+            val = (val << Meta::LEVEL_BITS[i]) | index as u64;
+        }
+        VAddr::new(val << Meta::PAGE_BITS)
+    }
+}
+
+impl<Meta: VmMeta> PhysMapper<Meta> for RecursiveMapper {
+    #[inline]
+    fn map_frame(&self, _ppn: PPN<Meta>) -> Option<NonNull<u8>> {
+        None
+    }
+
+    #[inline]
+    fn map_table(&self, _ppn: PPN<Meta>, pos: Pos<Meta>) -> NonNull<Pte<Meta>> {
+        let vaddr = self.table_vaddr(pos.vpn, pos.level);
+        NonNull::new(unsafe { vaddr.as_mut_ptr() }).expect("recursive table address is null")
+    }
+}
+
+#[test]
+fn test_recursive() {
+    use crate::test_meta::Sv39;
+
+    let mapper = RecursiveMapper { index: 0x1ff };
+    let vpn = VPN::<Sv39>::new((1 << 18) | (2 << 9) | 3);
+    assert_eq!(mapper.table_vaddr(vpn, 2).val(), 0xffff_ffff_ffff_f000);
+    assert_eq!(mapper.table_vaddr(vpn, 1).val(), 0xffff_ffff_ffe0_1000);
+    assert_eq!(mapper.table_vaddr(vpn, 0).val(), 0xffff_ffff_c020_2000);
+}
+
+#[test]
+#[should_panic]
+fn test_recursive_null() {
+    use crate::test_meta::Sv39;
+
+    let mapper = RecursiveMapper { index: 0 };
+    let _ = PhysMapper::<Sv39>::map_table(&mapper, PPN::new(0), Pos::new(VPN::ZERO, 0));
+}
@@ -1,14 +1,14 @@
-﻿use crate::{VmFlags, VmMeta, PPN};
+﻿use crate::{RawPte, VmFlags, VmMeta, PPN};
 use core::{fmt, marker::PhantomData};
 
 /// 页表项。
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-pub struct Pte<Meta: VmMeta>(pub usize, pub(crate) PhantomData<Meta>);
+pub struct Pte<Meta: VmMeta>(pub Meta::Raw, pub(crate) PhantomData<Meta>);
 
 impl<Meta: VmMeta> Pte<Meta> {
     /// 空白页表项。
-    pub const ZERO: Self = Self(0, PhantomData);
+    pub const ZERO: Self = Self(Meta::Raw::ZERO, PhantomData);
 
     /// 获取页表项指向的物理页号。
     #[inline]
@@ -16,6 +16,14 @@ impl<Meta: VmMeta> Pte<Meta> {
         Meta::ppn(self.0)
     }
 
+    /// 获取 `level` 级叶子页表项指向的物理页号。
+    ///
+    /// 不包括占用物理页号字段的特性位，见 [`MmuMeta::leaf_ppn_flags`](crate::MmuMeta::leaf_ppn_flags)。
+    #[inline]
+    pub fn leaf_ppn(self, level: usize) -> PPN<Meta> {
+        Meta::ppn(self.0 & !Meta::leaf_ppn_flags(level))
+    }
+
     /// 如果页表项指向一个页而非子页表，返回 `true`。
     #[inline]
     pub fn is_leaf(self) -> bool {
@@ -28,6 +36,14 @@ impl<Meta: VmMeta> Pte<Meta> {
         Meta::is_huge(self.0, level)
     }
 
+    /// 如果页表项是共享的，返回 `true`。
+    ///
+    /// 见 [`MmuMeta::SHARED_FLAG`](crate::MmuMeta::SHARED_FLAG)。
+    #[inline]
+    pub fn is_shared(self) -> bool {
+        self.0 & Meta::SHARED_FLAG != Meta::Raw::ZERO
+    }
+
     /// 如果页表项有效，返回 `true`。
     #[inline]
     pub fn is_valid(self) -> bool {
@@ -40,6 +56,15 @@ impl<Meta: VmMeta> Pte<Meta> {
         Meta::clear_ppn(&mut self.0);
         unsafe { VmFlags::from_raw(self.0) }
     }
+
+    /// 取出 `level` 级叶子页表项的属性。
+    ///
+    /// 包括占用物理页号字段的特性位，见 [`MmuMeta::leaf_ppn_flags`](crate::MmuMeta::leaf_ppn_flags)。
+    #[inline]
+    pub fn leaf_flags(self, level: usize) -> VmFlags<Meta> {
+        let ppn_flags = self.0 & Meta::leaf_ppn_flags(level);
+        unsafe { VmFlags::from_raw(self.flags().val() | ppn_flags) }
+    }
 }
 
 impl<Meta: VmMeta> fmt::Debug for Pte<Meta> {
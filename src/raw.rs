@@ -0,0 +1,108 @@
+﻿use core::{
+    fmt,
+    hash::Hash,
+    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, Shr},
+};
+
+/// 页表项的存储类型。
+///
+/// 页表项的宽度由页表格式决定，与平台的 `usize` 无关。
+pub trait RawPte:
+    'static
+    + Copy
+    + Ord
+    + Hash
+    + fmt::Debug
+    + fmt::LowerHex
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + BitAndAssign
+    + BitOrAssign
+    + BitXorAssign
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+{
+    /// 全零。
+    const ZERO: Self;
+
+    /// 最低位为 1。
+    const ONE: Self;
+
+    /// 位数。
+    const BITS: usize;
+
+    /// 从 `u64` 转换，超出的高位被截断。
+    fn from_u64(value: u64) -> Self;
+
+    /// 从 `usize` 转换，超出的高位被截断。
+    fn from_usize(value: usize) -> Self;
+
+    /// 转换为 `usize`，超出的高位被截断。
+    fn to_usize(self) -> usize;
+
+    /// 转换为 `u64`，超出的高位被截断。
+    fn to_u64(self) -> u64;
+
+    /// 原子地将 `ptr` 指向的值与 `mask` 按位与，返回原来的值。
+    ///
+    /// 目标平台不支持这个宽度的原子操作时返回 `None`，不修改 `ptr` 指向的值。
+    ///
+    /// # Safety
+    ///
+    /// `ptr` 必须有效，并按 `Self` 对齐。
+    unsafe fn fetch_and(ptr: *mut Self, mask: Self) -> Option<Self>;
+}
+
+macro_rules! impl_raw_pte {
+    ($($ty:ty: $atomic:ident, $width:literal;)+) => {
+        $(
+            impl RawPte for $ty {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const BITS: usize = <$ty>::BITS as _;
+
+                #[inline]
+                fn from_u64(value: u64) -> Self {
+                    value as _
+                }
+
+                #[inline]
+                fn from_usize(value: usize) -> Self {
+                    value as _
+                }
+
+                #[inline]
+                fn to_usize(self) -> usize {
+                    self as _
+                }
+
+                #[inline]
+                fn to_u64(self) -> u64 {
+                    self as _
+                }
+
+                #[inline]
+                unsafe fn fetch_and(ptr: *mut Self, mask: Self) -> Option<Self> {
+                    #[cfg(target_has_atomic = $width)]
+                    {
+                        use core::sync::atomic::{$atomic, Ordering::AcqRel};
+                        Some(unsafe { $atomic::from_ptr(ptr) }.fetch_and(mask, AcqRel))
+                    }
+                    #[cfg(not(target_has_atomic = $width))]
+                    {
+                        let _ = (ptr, mask);
+                        None
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_raw_pte! {
+    u32: AtomicU32, "32";
+    u64: AtomicU64, "64";
+    usize: AtomicUsize, "ptr";
+}
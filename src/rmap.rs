@@ -0,0 +1,324 @@
+﻿use crate::{
+    FrameAllocator, PageTableError, PageTableMut, PageTableRef, PhysMapper, VAddr, VmFlags, VmMeta,
+    PPN, VPN,
+};
+use core::ops::Range;
+
+/// 扫描 `tables` 中的每个页表，查找所有映射到物理页 `ppn` 的虚页。
+///
+/// `tables` 提供页表的标识和页表本身。每个找到的虚页以页表标识、虚页号、所在叶子页表项的级别和属性报告给 `found`。
+/// 见 [`PageTableRef::reverse_lookup`]。
+pub fn reverse_lookup<'a, Meta: VmMeta, Id: Copy>(
+    tables: impl IntoIterator<Item = (Id, PageTableRef<'a, Meta>)>,
+    ppn: PPN<Meta>,
+    mapper: &impl PhysMapper<Meta>,
+    mut found: impl FnMut(Id, VPN<Meta>, usize, VmFlags<Meta>),
+) {
+    for (id, pt) in tables {
+        pt.reverse_lookup(ppn, mapper, |vpn, level, flags| {
+            found(id, vpn, level, flags)
+        });
+    }
+}
+
+/// 反向映射索引。
+///
+/// 记录 `Id` 标识的若干页表中的叶子映射，不需要扫描页表就能查找映射到一个物理页的所有虚页。
+/// 最多记录 `N` 个叶子页表项。
+///
+/// 只有通过索引的 [`map`](Self::map) 和 [`unmap`](Self::unmap) 建立和撤销的映射会被自动记录。
+/// 以其他方式修改页表后，使用 [`rebuild`](Self::rebuild) 重新记录这个页表。
+pub struct RmapIndex<Meta: VmMeta, Id, const N: usize> {
+    entries: [Option<Entry<Meta, Id>>; N],
+}
+
+#[derive(Clone, Copy)]
+struct Entry<Meta: VmMeta, Id> {
+    id: Id,
+    vpn: VPN<Meta>,
+    ppn: PPN<Meta>,
+    flags: VmFlags<Meta>,
+    level: usize,
+}
+
+impl<Meta: VmMeta, Id: Copy + Eq, const N: usize> RmapIndex<Meta, Id, N> {
+    /// 新建空的索引。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            entries: [const { None }; N],
+        }
+    }
+
+    /// 记录的叶子页表项数量。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    /// 如果没有记录任何叶子页表项，返回 `true`。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(Option::is_none)
+    }
+
+    /// 查找所有映射到物理页 `ppn` 的虚页。
+    ///
+    /// 迭代出页表标识、虚页号、所在叶子页表项的级别和属性。大页中只给出映射到 `ppn` 的那一个虚页。
+    pub fn query(
+        &self,
+        ppn: PPN<Meta>,
+    ) -> impl Iterator<Item = (Id, VPN<Meta>, usize, VmFlags<Meta>)> + '_ {
+        self.entries.iter().flatten().filter_map(move |e| {
+            let offset = ppn.val().wrapping_sub(e.ppn.val());
+            (offset < Meta::pages_in_page(e.level)).then_some((
+                e.id,
+                e.vpn + offset,
+                e.level,
+                e.flags,
+            ))
+        })
+    }
+
+    /// 在 `id` 标识的页表 `pt` 中建立映射并记录。
+    ///
+    /// 如果剩余容量不足以记录所有新的叶子页表项，返回 [`PageTableError::RegistryFull`]，不修改页表。
+    /// 出错时这次调用已经建立的映射仍然被记录，已经记录过的虚页不会重复记录。其他参数见 [`PageTableMut::map`]。
+    #[allow(clippy::too_many_arguments)]
+    pub fn map(
+        &mut self,
+        id: Id,
+        pt: &mut PageTableMut<Meta>,
+        range: Range<VPN<Meta>>,
+        ppn: PPN<Meta>,
+        flags: VmFlags<Meta>,
+        level: usize,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        let pages = Meta::pages_in_page(level.min(Meta::MAX_LEVEL));
+        let count = range.end.val().saturating_sub(range.start.val()) / pages;
+        if count > (N - self.len()) as u64 {
+            return Err(PageTableError::RegistryFull);
+        }
+        let ans = pt.map(range.clone(), ppn, flags, level, alloc, mapper);
+        let flags = unsafe { VmFlags::from_raw(Meta::leaf_flags(flags.val(), level)) };
+        for i in 0..count {
+            let vpn = range.start + i * pages;
+            let ppn = ppn + i * pages;
+            // 出错时只记录确实建立了的映射，原有的映射可能已经记录过
+            if ans.is_err()
+                && (self.contains(id, vpn)
+                    || pt.translate(VAddr::new(vpn.base().val()), mapper)
+                        != Some((ppn.base(), flags, level)))
+            {
+                continue;
+            }
+            self.insert(Entry {
+                id,
+                vpn,
+                ppn,
+                flags,
+                level,
+            });
+        }
+        ans
+    }
+
+    /// 撤销 `id` 标识的页表 `pt` 中 `range` 范围内的所有映射，并删除它们的记录。
+    ///
+    /// 见 [`PageTableMut::unmap`]。
+    #[allow(clippy::too_many_arguments)]
+    pub fn unmap(
+        &mut self,
+        id: Id,
+        pt: &mut PageTableMut<Meta>,
+        range: Range<VPN<Meta>>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        mut unmapped: impl FnMut(VPN<Meta>, PPN<Meta>, usize),
+        freed: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        pt.unmap(
+            range,
+            alloc,
+            mapper,
+            |vpn, ppn, level| {
+                self.remove(id, vpn);
+                unmapped(vpn, ppn, level);
+            },
+            freed,
+        )
+    }
+
+    /// 删除 `id` 标识的页表的所有记录。
+    pub fn forget(&mut self, id: Id) {
+        for entry in &mut self.entries {
+            if entry.is_some_and(|e| e.id == id) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// 扫描 `id` 标识的页表 `pt`，重新记录其中所有的叶子映射。
+    ///
+    /// 如果剩余容量不足，返回 [`PageTableError::RegistryFull`]，此时 `id` 的记录被清空。
+    pub fn rebuild(
+        &mut self,
+        id: Id,
+        pt: &PageTableRef<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        self.forget(id);
+        if pt.mappings(mapper).count() > N - self.len() {
+            return Err(PageTableError::RegistryFull);
+        }
+        for m in pt.mappings(mapper) {
+            self.insert(Entry {
+                id,
+                vpn: m.vpn,
+                ppn: m.ppn,
+                flags: m.flags,
+                level: m.level,
+            });
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn insert(&mut self, entry: Entry<Meta, Id>) {
+        let slot = self.entries.iter_mut().find(|e| e.is_none()).unwrap();
+        *slot = Some(entry);
+    }
+
+    #[inline]
+    fn contains(&self, id: Id, vpn: VPN<Meta>) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.is_some_and(|e| e.id == id && e.vpn == vpn))
+    }
+
+    #[inline]
+    fn remove(&mut self, id: Id, vpn: VPN<Meta>) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.is_some_and(|e| e.id == id && e.vpn == vpn))
+        {
+            *entry = None;
+        }
+    }
+}
+
+impl<Meta: VmMeta, Id: Copy + Eq, const N: usize> Default for RmapIndex<Meta, Id, N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_rmap() {
+    use crate::{
+        test_meta::{frame_ptr, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<6>::new();
+    let roots = [
+        frames.allocate_one().unwrap(),
+        frames.allocate_one().unwrap(),
+    ];
+    let mut a = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[0])) };
+    let mut b = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[1])) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    let mut index = RmapIndex::<Sv39, usize, 4>::new();
+
+    index
+        .map(
+            0,
+            &mut a,
+            VPN::new(0x200)..VPN::new(0x202),
+            PPN::new(0x8000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    // 重复映射同一范围失败，已有的记录不会重复
+    assert_eq!(
+        index.map(
+            0,
+            &mut a,
+            VPN::new(0x200)..VPN::new(0x202),
+            PPN::new(0x8000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper,
+        ),
+        Err(PageTableError::AlreadyMapped)
+    );
+    assert_eq!(index.len(), 2);
+    index
+        .map(
+            1,
+            &mut b,
+            VPN::new(0x400)..VPN::new(0x600),
+            PPN::new(0x8000),
+            flags,
+            1,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    assert_eq!(index.len(), 3);
+
+    let expected = [
+        (0, VPN::new(0x201), 0, flags),
+        (1, VPN::new(0x401), 1, flags),
+    ];
+    let mut ans = [(0, VPN::ZERO, 0, flags); 2];
+    let mut len = 0;
+    reverse_lookup(
+        [(0, a.as_ref()), (1, b.as_ref())],
+        PPN::new(0x8001),
+        &IdentityMapper,
+        |id, vpn, level, flags| {
+            ans[len] = (id, vpn, level, flags);
+            len += 1;
+        },
+    );
+    assert_eq!(ans[..len], expected);
+    assert!(index.query(PPN::new(0x8001)).eq(expected));
+
+    index
+        .unmap(
+            0,
+            &mut a,
+            VPN::new(0x201)..VPN::new(0x202),
+            &mut frames,
+            &IdentityMapper,
+            |_, _, _| {},
+            |_, _| {},
+        )
+        .unwrap();
+    assert!(index.query(PPN::new(0x8001)).eq([expected[1]]));
+    assert_eq!(
+        index.map(
+            0,
+            &mut a,
+            VPN::new(0x300)..VPN::new(0x303),
+            PPN::new(0x9000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper,
+        ),
+        Err(PageTableError::RegistryFull)
+    );
+    index.forget(1);
+    index.rebuild(1, &b.as_ref(), &IdentityMapper).unwrap();
+    assert_eq!(index.len(), 2);
+}
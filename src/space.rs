@@ -0,0 +1,321 @@
+﻿use crate::{
+    table::{map_child, zero_table},
+    Decorator, FlushSet, FrameAllocator, PAddr, PageTableError, PageTableMut, PageTableRef,
+    PhysMapper, Pos, Pte, SharedTemplate, Update, VAddr, VmFlags, VmMeta, PPN, VPN,
+};
+use core::{ops::Range, ptr::NonNull};
+
+/// 释放叶子页表项指向的物理页的回调。
+///
+/// 参数是地址空间的分配器、叶子页表项的起始虚页号、物理页号和级别。
+pub type ReleaseLeaf<Meta, A> = fn(&mut A, VPN<Meta>, PPN<Meta>, usize);
+
+/// 地址空间。
+///
+/// 持有根页表和通过它建立的所有中间页表，销毁时回收这些页表页帧。
+pub struct AddressSpace<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> {
+    root: PPN<Meta>,
+    alloc: A,
+    mapper: M,
+    release: Option<ReleaseLeaf<Meta, A>>,
+}
+
+impl<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> AddressSpace<Meta, A, M> {
+    /// 从 `alloc` 分配根页表，新建一个空的地址空间。
+    ///
+    /// 页表通过 `mapper` 访问。如果 `mapper` 不能直接访问物理页，`alloc` 分配的页帧必须已经清零。
+    pub fn new(mut alloc: A, mapper: M) -> Result<Self, PageTableError> {
+        let root = alloc.allocate_one().ok_or(PageTableError::OutOfFrames)?;
+        unsafe { zero_table(&mapper, root, Meta::MAX_LEVEL) };
+        Ok(Self {
+            root,
+            alloc,
+            mapper,
+            release: None,
+        })
+    }
+
+    /// 从 `alloc` 分配根页表，新建一个链接了 `template` 中共享映射的地址空间，并登记到 `template`。
+    ///
+    /// 共享的映射属于 `template`，销毁地址空间时不会回收。
+    ///
+    /// # Safety
+    ///
+    /// 销毁地址空间时会回收根页表，因此销毁前必须以 [`root`](Self::root) 从 `template` 注销，
+    /// 否则 [`SharedTemplate::propagate`] 会写入已经回收的页帧。
+    pub unsafe fn from_template<const N: usize>(
+        alloc: A,
+        mapper: M,
+        template: &mut SharedTemplate<Meta, N>,
+    ) -> Result<Self, PageTableError> {
+        let space = Self::new(alloc, mapper)?;
+        template.register(space.root, &space.mapper)?;
+        Ok(space)
+    }
+
+    /// 设置销毁地址空间时释放叶子页表项指向的物理页的方式。
+    ///
+    /// 默认不释放叶子页表项指向的物理页。
+    #[inline]
+    pub fn release_leaves(mut self, release: ReleaseLeaf<Meta, A>) -> Self {
+        self.release = Some(release);
+        self
+    }
+
+    /// 根页表的物理页号。
+    #[inline]
+    pub const fn root(&self) -> PPN<Meta> {
+        self.root
+    }
+
+    /// 将 `range` 范围的虚页以 `level` 级页映射到从 `ppn` 开始的连续物理页。
+    ///
+    /// 见 [`PageTableMut::map`]。
+    #[inline]
+    pub fn map(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        ppn: PPN<Meta>,
+        flags: VmFlags<Meta>,
+        level: usize,
+    ) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.map(range, ppn, flags, level, alloc, mapper)
+    }
+
+    /// 将 `range` 范围的虚页映射到从 `ppn` 开始的连续物理页，每一步都使用能用的最大的页。
+    ///
+    /// 见 [`PageTableMut::map_auto`]。
+    #[inline]
+    pub fn map_auto(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        ppn: PPN<Meta>,
+        flags: VmFlags<Meta>,
+    ) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.map_auto(range, ppn, flags, alloc, mapper)
+    }
+
+    /// 撤销 `range` 范围内的所有映射。
+    ///
+    /// 见 [`PageTableMut::unmap`]。
+    #[inline]
+    pub fn unmap(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        unmapped: impl FnMut(VPN<Meta>, PPN<Meta>, usize),
+        freed: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.unmap(range, alloc, mapper, unmapped, freed)
+    }
+
+    /// 修改 `range` 范围内所有映射的属性。
+    ///
+    /// 见 [`PageTableMut::protect`]。
+    #[inline]
+    pub fn protect(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        set: VmFlags<Meta>,
+        clear: VmFlags<Meta>,
+        protected: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.protect(range, set, clear, alloc, mapper, protected)
+    }
+
+    /// 将 `vpn` 所在的 `level` 级大页拆分为一个 `level - 1` 级页表。
+    ///
+    /// 见 [`PageTableMut::split`]。
+    #[inline]
+    pub fn split(&mut self, vpn: VPN<Meta>, level: usize) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.split(vpn, level, alloc, mapper)
+    }
+
+    /// 尝试将 `vpn` 所在的 `level - 1` 级页表合并为一个 `level` 级大页。
+    ///
+    /// 见 [`PageTableMut::try_collapse`]。
+    #[inline]
+    pub fn try_collapse(&mut self, vpn: VPN<Meta>, level: usize) -> Result<bool, PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.try_collapse(vpn, level, alloc, mapper)
+    }
+
+    /// 扫描 `range` 范围，将所有能合并的页表合并为大页。
+    ///
+    /// 见 [`PageTableMut::collapse`]。
+    #[inline]
+    pub fn collapse(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        collapsed: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.collapse(range, alloc, mapper, collapsed)
+    }
+
+    /// 处理对 `vaddr` 的写时复制页的写入。
+    ///
+    /// 见 [`PageTableMut::resolve_cow_fault`]。
+    #[inline]
+    pub fn resolve_cow_fault(
+        &mut self,
+        vaddr: VAddr<Meta>,
+    ) -> Result<Option<PPN<Meta>>, PageTableError> {
+        let (mut pt, alloc, mapper) = self.parts();
+        pt.resolve_cow_fault(vaddr, alloc, mapper)
+    }
+
+    /// 扫描 `range` 范围，读取并清除所有叶子页表项的访问位。
+    ///
+    /// 见 [`PageTableMut::harvest_accessed`]。
+    #[inline]
+    pub fn harvest_accessed<const N: usize>(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        harvested: impl FnMut(VPN<Meta>, PPN<Meta>, usize, bool),
+    ) -> Result<FlushSet<Meta, N>, PageTableError> {
+        let (mut pt, _, mapper) = self.parts();
+        pt.harvest_accessed(range, mapper, harvested)
+    }
+
+    /// 查询虚地址 `vaddr` 映射到的物理地址。
+    ///
+    /// 见 [`PageTableRef::translate`]。
+    #[inline]
+    pub fn translate(&self, vaddr: VAddr<Meta>) -> Option<(PAddr<Meta>, VmFlags<Meta>, usize)> {
+        self.table().translate(vaddr, &self.mapper)
+    }
+
+    /// 借出只读的根页表。
+    #[inline]
+    pub fn table(&self) -> PageTableRef<'_, Meta> {
+        unsafe { PageTableRef::from_root(self.root_ptr()) }
+    }
+
+    /// 借出可变的根页表。
+    ///
+    /// # Safety
+    ///
+    /// 地址空间销毁时会把页表中所有不共享的中间页表回收到它的分配器。
+    /// 因此通过它建立的中间页表必须从地址空间的分配器分配，也不能链接不属于这个地址空间的页表。
+    #[inline]
+    pub unsafe fn table_mut(&mut self) -> PageTableMut<'_, Meta> {
+        PageTableMut::from_root(self.root_ptr())
+    }
+
+    /// 同时借出可变的根页表、分配器和物理页访问方式。
+    #[inline]
+    fn parts(&mut self) -> (PageTableMut<'_, Meta>, &mut A, &M) {
+        let pt = unsafe { PageTableMut::from_root(self.root_ptr()) };
+        (pt, &mut self.alloc, &self.mapper)
+    }
+
+    #[inline]
+    fn root_ptr(&self) -> NonNull<Pte<Meta>> {
+        self.mapper
+            .map_table(self.root, Pos::new(VPN::ZERO, Meta::MAX_LEVEL))
+    }
+}
+
+impl<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> Drop for AddressSpace<Meta, A, M> {
+    fn drop(&mut self) {
+        let release = self.release;
+        let (mut pt, alloc, mapper) = self.parts();
+        if let Some(release) = release {
+            pt.walk_mut(
+                Pos::new(VPN::ZERO, 0),
+                &mut LeafVisitor {
+                    alloc: &mut *alloc,
+                    mapper,
+                    release,
+                },
+            );
+        }
+        let range = pt.range();
+        let _ = pt.unmap(range, &mut *alloc, mapper, |_, _, _| {}, |_, _| {});
+        self.alloc.deallocate_one(self.root);
+    }
+}
+
+/// 将所有不共享的叶子页表项交给回调的访问器。
+struct LeafVisitor<'a, Meta: VmMeta, A, M> {
+    alloc: &'a mut A,
+    mapper: &'a M,
+    release: ReleaseLeaf<Meta, A>,
+}
+
+impl<Meta: VmMeta, A, M: PhysMapper<Meta>> Decorator<Meta> for LeafVisitor<'_, Meta, A, M> {
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            (self.release)(self.alloc, target.vpn, pte.ppn(), 0);
+        }
+        target.next()
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        let base = target.vpn.floor(level);
+        if pte.is_valid() && !pte.is_shared() {
+            (self.release)(self.alloc, base, pte.leaf_ppn(level), level);
+        }
+        Update::Target(Pos::new(base + Meta::pages_in_page(level), 0))
+    }
+}
+
+#[test]
+fn test_address_space() {
+    use crate::{
+        test_meta::{Frames, Sv39},
+        IdentityMapper,
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut frames = Frames::<8>::new();
+    let mut space = AddressSpace::<Sv39, _, _>::new(&mut frames, IdentityMapper)
+        .unwrap()
+        .release_leaves(|_, _, _, level| {
+            RELEASED.fetch_add(1 << (9 * level), Relaxed);
+        });
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    space
+        .map(VPN::new(0x200)..VPN::new(0x204), PPN::new(0x8000), flags, 0)
+        .unwrap();
+    space
+        .map(
+            VPN::new(0x400)..VPN::new(0x600),
+            PPN::new(0x10000),
+            flags,
+            1,
+        )
+        .unwrap();
+    space
+        .map(
+            VPN::new(3 << 18)..VPN::new((3 << 18) + 1),
+            PPN::new(0x9000),
+            flags,
+            0,
+        )
+        .unwrap();
+    assert_eq!(
+        space.translate(VAddr::new(0xc000_0123)),
+        Some((PAddr::new(0x900_0123), flags, 0))
+    );
+    drop(space);
+    assert_eq!(RELEASED.load(Relaxed), 4 + 512 + 1);
+    assert_eq!(frames.used(), 0);
+}
@@ -0,0 +1,199 @@
+﻿use super::{huge_only_flags, map_child, skip, Decorator, Pos, Update};
+use crate::{
+    FrameAllocator, PageTableError, PageTableMut, PageTableRef, PhysMapper, Pte, RawPte, VmFlags,
+    VmMeta, VPN,
+};
+use core::{marker::PhantomData, ops::Range, ptr::NonNull};
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 尝试将 `vpn` 所在的 `level - 1` 级页表合并为一个 `level` 级大页。
+    ///
+    /// 只有页表中所有页表项都是属性相同的叶子，并且指向按 `level` 级页对齐的连续物理页时才能合并。
+    /// 合并后的页表回收到 `alloc`，页表通过 `mapper` 访问。如果发生了合并，返回 `true`。
+    pub fn try_collapse(
+        &mut self,
+        vpn: VPN<Meta>,
+        level: usize,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<bool, PageTableError> {
+        if level == 0 || level > self.level {
+            return Err(PageTableError::InvalidLevel);
+        }
+        if !self.range().contains(&vpn) {
+            return Err(PageTableError::AddressOutOfRange);
+        }
+        let start = vpn.floor(level);
+        let mut ans = false;
+        let mut visitor = CollapseVisitor {
+            range: start..start + Meta::pages_in_page(level),
+            alloc,
+            mapper,
+            collapsed: |_, _| ans = true,
+            _phantom: PhantomData,
+        };
+        self.walk_mut(Pos::new(start, level - 1), &mut visitor);
+        Ok(ans)
+    }
+
+    /// 扫描 `range` 范围，将所有能合并的页表合并为大页。
+    ///
+    /// 自底向上合并，合并出的大页还可以继续合并为更高级的大页。只合并完全在 `range` 内的页表。
+    /// 每个合并出的大页以其起始虚页号和级别报告给 `collapsed`，供调用者刷新 TLB。
+    /// 合并条件和页表的回收见 [`try_collapse`](Self::try_collapse)。
+    pub fn collapse(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        collapsed: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        self.check_range(&range)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let start = range.start;
+        let mut visitor = CollapseVisitor {
+            range,
+            alloc,
+            mapper,
+            collapsed,
+            _phantom: PhantomData,
+        };
+        self.walk_mut(Pos::new(start, 0), &mut visitor);
+        Ok(())
+    }
+}
+
+struct CollapseVisitor<'a, Meta: VmMeta, A, M, C> {
+    range: Range<VPN<Meta>>,
+    alloc: &'a mut A,
+    mapper: &'a M,
+    collapsed: C,
+    _phantom: PhantomData<Meta>,
+}
+
+impl<Meta, A, M, C> Decorator<Meta> for CollapseVisitor<'_, Meta, A, M, C>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+    C: FnMut(VPN<Meta>, usize),
+{
+    #[inline]
+    fn arrive(&mut self, _pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        skip(target.vpn, target.level, self.range.end, target.level)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, _pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        Update::Target(skip(target.vpn, level, self.range.end, target.level))
+    }
+
+    fn leave(&mut self, level: usize, pte: &mut Pte<Meta>, table: &PageTableRef<Meta>) {
+        let range = table.range();
+        if range.start < self.range.start || range.end > self.range.end {
+            return;
+        }
+        if let Some(huge) = collapse_pte(table) {
+            self.alloc.deallocate_one(pte.ppn());
+            *pte = huge;
+            (self.collapsed)(range.start, level);
+        }
+    }
+}
+
+/// 如果页表能合并为一个大页，返回指向这个大页的上一级页表项。
+fn collapse_pte<Meta: VmMeta>(table: &PageTableRef<Meta>) -> Option<Pte<Meta>> {
+    // 0 级页表项总是指向物理页，有的方案中它们的形式与表项相同
+    let level = table.level();
+    let is_leaf = |pte: Pte<Meta>| pte.is_valid() && (level == 0 || pte.is_leaf());
+    let first = table[0];
+    if !is_leaf(first) || !Meta::allow_huge(level + 1) {
+        return None;
+    }
+    let (ppn, flags) = (first.leaf_ppn(level), first.leaf_flags(level));
+    if ppn.val() & (Meta::pages_in_page(level + 1) - 1) != 0 {
+        return None;
+    }
+    // 大页特有的特性位在这一级页表项中有其他含义（例如 x86 的 PAT），不能合并
+    if flags.val() & huge_only_flags::<Meta>(level, level + 1) != Meta::Raw::ZERO {
+        return None;
+    }
+    let pages = Meta::pages_in_page(level);
+    let contiguous = table.mem.iter().enumerate().all(|(i, pte)| {
+        is_leaf(*pte)
+            && pte.leaf_flags(level) == flags
+            && pte.leaf_ppn(level) == ppn + i as u64 * pages
+    });
+    contiguous.then(|| {
+        let flags = unsafe { VmFlags::<Meta>::from_raw(Meta::leaf_flags(flags.val(), level + 1)) };
+        flags.build_pte(ppn)
+    })
+}
+
+#[test]
+fn test_collapse() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper, VAddr, PPN,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    for (start, end, ppn) in [
+        (0x200, 0x3ff, 0x8000),
+        (0x3ff, 0x400, 0x81ff),
+        (0x400, 0x500, 0x8400),
+        (0x500, 0x600, 0x9000),
+    ] {
+        pt.map(
+            VPN::new(start)..VPN::new(end),
+            PPN::new(ppn),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    }
+    assert_eq!(frames.used(), 4);
+
+    // 物理页不连续
+    assert_eq!(
+        pt.try_collapse(VPN::new(0x400), 1, &mut frames, &IdentityMapper),
+        Ok(false)
+    );
+    let mut collapsed = None;
+    pt.collapse(
+        VPN::new(0)..VPN::new(0x4_0000),
+        &mut frames,
+        &IdentityMapper,
+        |vpn, level| {
+            assert!(collapsed.is_none());
+            collapsed = Some((vpn.val(), level));
+        },
+    )
+    .unwrap();
+    assert_eq!(collapsed, Some((0x200, 1)));
+    assert_eq!(frames.used(), 3);
+    assert_eq!(
+        pt.translate(VAddr::new(0x23_4567), &IdentityMapper),
+        Some((crate::PAddr::new(0x803_4567), flags, 1))
+    );
+    assert_eq!(
+        pt.try_collapse(VPN::new(0x234), 1, &mut frames, &IdentityMapper),
+        Ok(false)
+    );
+}
@@ -0,0 +1,307 @@
+﻿use super::{map_child, zero_table, Decorator, Pos, Update};
+use crate::{
+    FrameAllocator, PageTableError, PageTableMut, PhysMapper, Pte, RawPte, VAddr, VmFlags, VmMeta,
+    PPN, VPN,
+};
+use core::ptr::NonNull;
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 以写时复制的方式复制页表，返回新页表所在的物理页号。
+    ///
+    /// 新页表和中间页表从 `alloc` 分配，与 `self` 共享所有叶子指向的物理页。
+    /// 可写的叶子在两个页表中都被改为只读并标记 [`MmuMeta::COW_FLAG`](crate::MmuMeta::COW_FLAG)，
+    /// `self` 中每个被修改的叶子在修改时以其起始虚页号和级别报告给 `protected`，供调用者刷新 TLB，出错时也是如此。
+    /// 成功后，`self` 中每个共享的叶子以其起始虚页号、物理页号和级别报告给 `shared`，供调用者增加物理页的引用计数。
+    ///
+    /// 共享的页表项（见 [`share`](Self::share)）原样链接到新页表，不报告给 `shared`，只能出现在 `self` 的最高级。
+    /// 新页表不会登记到 `self` 所属的 [`SharedTemplate`](crate::SharedTemplate)，模板之后新增的页表项不会传播到新页表，
+    /// 需要时由调用者以返回的物理页号调用 [`SharedTemplate::register`](crate::SharedTemplate::register)。
+    ///
+    /// `mapper` 必须能直接访问物理页。出错时新页表被回收，`self` 中已经标记的写时复制页保持不变。
+    pub fn fork(
+        &mut self,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        mut shared: impl FnMut(VPN<Meta>, PPN<Meta>, usize),
+        protected: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<PPN<Meta>, PageTableError> {
+        if Meta::COW_FLAG == Meta::Raw::ZERO {
+            return Err(PageTableError::Unsupported);
+        }
+        let root = alloc.allocate_one().ok_or(PageTableError::OutOfFrames)?;
+        let Some(ptr) = (unsafe { zero_table(mapper, root, self.level) }) else {
+            alloc.deallocate_one(root);
+            return Err(PageTableError::Unsupported);
+        };
+        let range = self.range();
+        let child = unsafe { PageTableMut::from_raw_parts(ptr, range.start, self.level) };
+        let mut visitor = ForkVisitor {
+            child,
+            alloc,
+            mapper,
+            protected,
+            ans: Ok(()),
+        };
+        self.walk_mut(Pos::new(range.start, 0), &mut visitor);
+        let ForkVisitor {
+            mut child,
+            alloc,
+            ans,
+            ..
+        } = visitor;
+        match ans {
+            Ok(()) => {
+                let level = self.level;
+                for m in self.mappings(mapper) {
+                    if !self.mem[m.vpn.index_in(level)].is_shared() {
+                        shared(m.vpn, m.ppn, m.level);
+                    }
+                }
+                Ok(root)
+            }
+            Err(e) => {
+                let _ = child.unmap(range, alloc, mapper, |_, _, _| {}, |_, _| {});
+                alloc.deallocate_one(root);
+                Err(e)
+            }
+        }
+    }
+
+    /// 处理对 `vaddr` 的写时复制页的写入。
+    ///
+    /// 从 `alloc` 分配新的物理页，通过 `mapper` 复制原来的内容，然后将新的物理页可写地映射到原来的位置。
+    /// 大页整体复制。成功后返回原来的物理页号，供调用者减少引用计数，并需要刷新 `vaddr` 的 TLB。
+    ///
+    /// 如果 `vaddr` 所在的页不是写时复制页，返回 `None`。
+    pub fn resolve_cow_fault(
+        &mut self,
+        vaddr: VAddr<Meta>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<Option<PPN<Meta>>, PageTableError> {
+        let vpn = vaddr.floor();
+        if !self.range().contains(&vpn) {
+            return Err(PageTableError::AddressOutOfRange);
+        }
+        let mut visitor = CowVisitor {
+            alloc,
+            mapper,
+            ans: Err(PageTableError::NotMapped),
+        };
+        self.walk_mut(Pos::new(vpn, 0), &mut visitor);
+        visitor.ans
+    }
+}
+
+struct ForkVisitor<'a, Meta: VmMeta, A, M, P> {
+    child: PageTableMut<'a, Meta>,
+    alloc: &'a mut A,
+    mapper: &'a M,
+    protected: P,
+    ans: Result<(), PageTableError>,
+}
+
+impl<Meta, A, M, P> Decorator<Meta> for ForkVisitor<'_, Meta, A, M, P>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+    P: FnMut(VPN<Meta>, usize),
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        let pages = Meta::pages_in_page(target.level);
+        // 共享的页表项直接链接到新页表
+        if pte.is_shared() {
+            if target.level != self.child.level {
+                self.ans = Err(PageTableError::SharedTable);
+                return Pos::stop();
+            }
+            self.child.mem[target.vpn.index_in(target.level)] = *pte;
+        } else if pte.is_valid() {
+            let ppn = pte.leaf_ppn(target.level);
+            let mut flags = pte.leaf_flags(target.level).val();
+            if Meta::is_writable(flags) || flags & Meta::COW_FLAG != Meta::Raw::ZERO {
+                flags = Meta::set_writable(flags, false) | Meta::COW_FLAG;
+                let new = unsafe { VmFlags::from_raw(flags) }.build_pte(ppn);
+                if new != *pte {
+                    *pte = new;
+                    (self.protected)(target.vpn, target.level);
+                }
+            }
+            if let Err(e) = self.child.map(
+                target.vpn..target.vpn + pages,
+                ppn,
+                pte.leaf_flags(target.level),
+                target.level,
+                self.alloc,
+                self.mapper,
+            ) {
+                self.ans = Err(e);
+                return Pos::stop();
+            }
+        }
+        Pos::new(target.vpn + pages, 0)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        let base = target.vpn.floor(level);
+        // 大页转为访问大页页表项本身
+        Update::Target(if pte.is_valid() {
+            Pos::new(base, level)
+        } else {
+            Pos::new(base + Meta::pages_in_page(level), 0)
+        })
+    }
+}
+
+struct CowVisitor<'a, Meta: VmMeta, A, M> {
+    alloc: &'a mut A,
+    mapper: &'a M,
+    ans: Result<Option<PPN<Meta>>, PageTableError>,
+}
+
+impl<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> CowVisitor<'_, Meta, A, M> {
+    /// 复制从 `src` 开始的 `count` 个物理页，返回新的物理页号。
+    fn copy(&mut self, src: PPN<Meta>, count: usize) -> Result<PPN<Meta>, PageTableError> {
+        let dst = self
+            .alloc
+            .allocate(count, count)
+            .ok_or(PageTableError::OutOfFrames)?;
+        for i in 0..count {
+            match (
+                self.mapper.map_frame(src + i as u64),
+                self.mapper.map_frame(dst + i as u64),
+            ) {
+                (Some(src), Some(dst)) => unsafe {
+                    core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_ptr(), 1 << Meta::PAGE_BITS)
+                },
+                _ => {
+                    self.alloc.deallocate(dst, count);
+                    return Err(PageTableError::Unsupported);
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
+impl<Meta, A, M> Decorator<Meta> for CowVisitor<'_, Meta, A, M>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if !pte.is_valid() {
+            return Pos::stop();
+        }
+        let flags = pte.leaf_flags(target.level).val();
+        if flags & Meta::COW_FLAG == Meta::Raw::ZERO {
+            self.ans = Ok(None);
+            return Pos::stop();
+        }
+        let old = pte.leaf_ppn(target.level);
+        self.ans = self
+            .copy(old, Meta::pages_in_page(target.level) as _)
+            .map(|ppn| {
+                let flags = Meta::set_writable(flags & !Meta::COW_FLAG, true);
+                *pte = unsafe { VmFlags::from_raw(flags) }.build_pte(ppn);
+                Some(old)
+            });
+        Pos::stop()
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        // 大页转为访问大页页表项本身
+        Update::Target(if pte.is_valid() {
+            Pos::new(target.vpn.floor(level), level)
+        } else {
+            Pos::stop()
+        })
+    }
+}
+
+#[test]
+fn test_cow() {
+    use crate::{
+        test_meta::{frame_ptr, map_pages, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<8>::new();
+    let root = frames.allocate_one().unwrap();
+    let data = frames.allocate_one().unwrap();
+    unsafe { frame_ptr(data).cast::<u8>().write(42) };
+    let mut pt = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(root)) };
+    let rw = unsafe { VmFlags::<Sv39>::from_raw(0b0111) };
+    let ro = unsafe { VmFlags::<Sv39>::from_raw(0b0011) };
+    let cow = unsafe { VmFlags::<Sv39>::from_raw(0b1_0000_0011) };
+    map_pages(
+        &mut pt,
+        &mut frames,
+        &[
+            (0x200..0x201, data.val(), rw, 0),
+            (0x201..0x202, 0x9000, ro, 0),
+        ],
+    );
+
+    let (mut count, mut protected) = (0, 0);
+    let child = pt
+        .fork(
+            &mut frames,
+            &IdentityMapper,
+            |_, _, _| count += 1,
+            |vpn, level| {
+                assert_eq!((vpn.val(), level), (0x200, 0));
+                protected += 1;
+            },
+        )
+        .unwrap();
+    assert_eq!((count, protected), (2, 1));
+    let mut child = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(child)) };
+    for pt in [&pt, &child] {
+        let translate = |vaddr| pt.translate(VAddr::new(vaddr), &IdentityMapper).unwrap();
+        assert_eq!(translate(0x20_0000), (data.base(), cow, 0));
+        assert_eq!(translate(0x20_1000).1, ro);
+    }
+
+    let vaddr = VAddr::new(0x20_0123);
+    assert_eq!(
+        child.resolve_cow_fault(vaddr, &mut frames, &IdentityMapper),
+        Ok(Some(data))
+    );
+    let (paddr, flags, _) = child.translate(vaddr, &IdentityMapper).unwrap();
+    assert_eq!(flags, rw);
+    assert_ne!(paddr.floor(), data);
+    assert_eq!(unsafe { frame_ptr(paddr.floor()).cast::<u8>().read() }, 42);
+    assert_eq!(
+        child.resolve_cow_fault(VAddr::new(0x20_1000), &mut frames, &IdentityMapper),
+        Ok(None)
+    );
+    assert_eq!(
+        child.resolve_cow_fault(VAddr::new(0x30_0000), &mut frames, &IdentityMapper),
+        Err(PageTableError::NotMapped)
+    );
+}
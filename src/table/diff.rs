@@ -0,0 +1,320 @@
+﻿use super::{same_leaf_flags, Pos};
+use crate::{PageTableError, PageTableRef, PhysMapper, Pte, VmMeta, VPN};
+use core::ops::Range;
+
+/// 两个页表之间的一处差异。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Change<Meta: VmMeta> {
+    /// 差异所在的虚页范围。
+    pub range: Range<VPN<Meta>>,
+    /// 差异的种类。
+    pub kind: ChangeKind,
+}
+
+/// 差异的种类。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ChangeKind {
+    /// 只在新页表中映射。
+    Added,
+    /// 只在旧页表中映射。
+    Removed,
+    /// 两边都映射，但物理页不同，或者只有部分虚页在两边都映射。
+    Remapped,
+    /// 两边映射到相同的物理页，但属性不同。
+    Reflagged,
+}
+
+/// 按虚页号从小到大迭代旧页表 `a` 到新页表 `b` 的差异。
+///
+/// 两个页表同步遍历，指向相同子页表的页表项直接跳过，不会进入子页表比较。页表通过 `mapper` 访问。
+/// 一侧的大页对应另一侧的多个小页时，整个大页范围作为一处差异报告；两侧映射相同时不报告。
+/// 相邻的同种差异合并为一处。
+///
+/// 如果 `a` 和 `b` 的级别不同，返回 [`PageTableError::InvalidLevel`]；
+/// 级别相同但覆盖范围不同，返回 [`PageTableError::AddressOutOfRange`]。
+pub fn diff<'a, Meta: VmMeta, M: PhysMapper<Meta>>(
+    a: &PageTableRef<'a, Meta>,
+    b: &PageTableRef<'a, Meta>,
+    mapper: M,
+) -> Result<Diff<'a, Meta, M>, PageTableError> {
+    if a.level != b.level {
+        return Err(PageTableError::InvalidLevel);
+    }
+    if a.base != b.base {
+        return Err(PageTableError::AddressOutOfRange);
+    }
+    Ok(Diff {
+        a: *a,
+        b: *b,
+        mapper,
+        next: Some(a.range().start),
+        pending: None,
+    })
+}
+
+/// 页表差异迭代器。
+///
+/// 每次查找下一处差异都从根页表开始，因此不需要额外的栈空间。
+pub struct Diff<'a, Meta: VmMeta, M> {
+    a: PageTableRef<'a, Meta>,
+    b: PageTableRef<'a, Meta>,
+    mapper: M,
+    next: Option<VPN<Meta>>,
+    pending: Option<Change<Meta>>,
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Diff<'_, Meta, M> {
+    /// 查找下一处未合并的差异。
+    fn find(&mut self) -> Option<Change<Meta>> {
+        let from = self.next?;
+        let ans = first_change(Some(self.a), Some(self.b), from, &self.mapper);
+        self.next = ans
+            .as_ref()
+            .map(|c| c.range.end)
+            .filter(|end| *end < self.a.range().end);
+        ans
+    }
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Iterator for Diff<'_, Meta, M> {
+    type Item = Change<Meta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut ans = self.pending.take().or_else(|| self.find())?;
+        while let Some(next) = self.find() {
+            if next.kind == ans.kind && next.range.start == ans.range.end {
+                ans.range.end = next.range.end;
+            } else {
+                self.pending = Some(next);
+                break;
+            }
+        }
+        Some(ans)
+    }
+}
+
+/// 页表项的种类。
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Invalid,
+    Leaf,
+    Table,
+}
+
+impl Node {
+    #[inline]
+    fn of<Meta: VmMeta>(pte: Pte<Meta>, level: usize) -> Self {
+        // 0 级页表项总是指向物理页，有的方案中它们的形式与表项相同
+        if !pte.is_valid() {
+            Self::Invalid
+        } else if level == 0 || pte.is_leaf() {
+            Self::Leaf
+        } else {
+            Self::Table
+        }
+    }
+}
+
+/// 在 `a` 和 `b` 中查找从 `from` 开始的第一处差异。
+///
+/// `a` 和 `b` 中至少一个存在，不存在的一侧视为所有页表项都无效。
+fn first_change<Meta: VmMeta>(
+    a: Option<PageTableRef<Meta>>,
+    b: Option<PageTableRef<Meta>>,
+    from: VPN<Meta>,
+    mapper: &impl PhysMapper<Meta>,
+) -> Option<Change<Meta>> {
+    let table = a.or(b)?;
+    let (base, level) = (table.base, table.level);
+    let pages = Meta::pages_in_page(level);
+    let first = (from.val().saturating_sub(base.val()) / pages) as usize;
+    for i in first..table.mem.len() {
+        let pa = a.map_or(Pte::ZERO, |t| t.mem[i]);
+        let pb = b.map_or(Pte::ZERO, |t| t.mem[i]);
+        // 相同的页表项指向相同的物理页或子页表
+        if pa == pb {
+            continue;
+        }
+        let entry = base + i as u64 * pages;
+        let range = entry.max(from)..entry + pages;
+        let child = |pte: Pte<Meta>| unsafe {
+            PageTableRef::from_raw_parts(
+                mapper.map_table(pte.ppn(), Pos::new(entry, level - 1)),
+                entry,
+                level - 1,
+            )
+        };
+        let kind = match (Node::of(pa, level), Node::of(pb, level)) {
+            (Node::Invalid, Node::Invalid) => None,
+            (Node::Leaf, Node::Invalid) => Some(ChangeKind::Removed),
+            (Node::Invalid, Node::Leaf) => Some(ChangeKind::Added),
+            (Node::Leaf, Node::Leaf) => {
+                if pa.leaf_ppn(level) != pb.leaf_ppn(level) {
+                    Some(ChangeKind::Remapped)
+                } else if pa.leaf_flags(level) != pb.leaf_flags(level) {
+                    Some(ChangeKind::Reflagged)
+                } else {
+                    None
+                }
+            }
+            (Node::Leaf, Node::Table) => compare_huge(pa, &child(pb), mapper, true),
+            (Node::Table, Node::Leaf) => compare_huge(pb, &child(pa), mapper, false),
+            (ka, kb) => {
+                let a = (ka == Node::Table).then(|| child(pa));
+                let b = (kb == Node::Table).then(|| child(pb));
+                match first_change(a, b, range.start, mapper) {
+                    Some(change) => return Some(change),
+                    None => continue,
+                }
+            }
+        };
+        if let Some(kind) = kind {
+            return Some(Change { range, kind });
+        }
+    }
+    None
+}
+
+/// 比较大页 `huge` 和覆盖同样范围的子页表 `table`。
+///
+/// `huge_is_old` 表示大页是否在旧页表中。映射相同时返回 `None`。
+fn compare_huge<Meta: VmMeta>(
+    huge: Pte<Meta>,
+    table: &PageTableRef<Meta>,
+    mapper: &impl PhysMapper<Meta>,
+    huge_is_old: bool,
+) -> Option<ChangeKind> {
+    let level = table.level + 1;
+    let base = table.range().start;
+    let (mut covered, mut same_ppn, mut same_flags) = (0, true, true);
+    for m in table.mappings(mapper) {
+        covered += Meta::pages_in_page(m.level);
+        same_ppn &= m.ppn == huge.leaf_ppn(level) + (m.vpn.val() - base.val());
+        same_flags &= same_leaf_flags(m.flags, m.level, huge.leaf_flags(level), level);
+    }
+    if covered == 0 {
+        Some(if huge_is_old {
+            ChangeKind::Removed
+        } else {
+            ChangeKind::Added
+        })
+    } else if covered < Meta::pages_in_table(table.level) || !same_ppn {
+        Some(ChangeKind::Remapped)
+    } else if !same_flags {
+        Some(ChangeKind::Reflagged)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_diff() {
+    use crate::{
+        test_meta::{frame_ptr, map_pages, Frames, Sv39},
+        FrameAllocator, IdentityMapper, PageTableMut, VmFlags,
+    };
+
+    let mut frames = Frames::<8>::new();
+    let roots = [
+        frames.allocate_one().unwrap(),
+        frames.allocate_one().unwrap(),
+    ];
+    let mut a = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[0])) };
+    let mut b = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[1])) };
+    let r = unsafe { VmFlags::<Sv39>::from_raw(0b0011) };
+    let rw = unsafe { VmFlags::<Sv39>::from_raw(0b0111) };
+    // 大页对应相同的小页、大页对应部分不同的小页、小页
+    map_pages(
+        &mut a,
+        &mut frames,
+        &[
+            (0x200..0x400, 0x8000, r, 1),
+            (0x400..0x600, 0x8200, r, 1),
+            (0x1000..0x1004, 0xa000, r, 0),
+        ],
+    );
+    map_pages(
+        &mut b,
+        &mut frames,
+        &[
+            (0x200..0x400, 0x8000, r, 0),
+            (0x400..0x5ff, 0x8200, r, 0),
+            (0x5ff..0x600, 0x9000, r, 0),
+            (0x1001..0x1002, 0xa001, rw, 0),
+            (0x1002..0x1003, 0xb000, r, 0),
+            (0x1004..0x1006, 0xa004, r, 0),
+        ],
+    );
+
+    let change = |start: u64, end: u64, kind| Change {
+        range: VPN::new(start)..VPN::new(end),
+        kind,
+    };
+    assert!(diff(&a.as_ref(), &b.as_ref(), &IdentityMapper)
+        .unwrap()
+        .eq([
+            change(0x400, 0x600, ChangeKind::Remapped),
+            change(0x1000, 0x1001, ChangeKind::Removed),
+            change(0x1001, 0x1002, ChangeKind::Reflagged),
+            change(0x1002, 0x1003, ChangeKind::Remapped),
+            change(0x1003, 0x1004, ChangeKind::Removed),
+            change(0x1004, 0x1006, ChangeKind::Added),
+        ]));
+    assert_eq!(
+        diff(&a.as_ref(), &a.as_ref(), &IdentityMapper)
+            .unwrap()
+            .count(),
+        0
+    );
+    let mem = [Pte::ZERO; 512];
+    let child = PageTableRef::new(&mem, VPN::ZERO, 1).unwrap();
+    assert!(matches!(
+        diff(&a.as_ref(), &child, &IdentityMapper),
+        Err(PageTableError::InvalidLevel)
+    ));
+}
+
+#[test]
+fn test_diff_shared() {
+    use crate::{
+        test_meta::{frame_ptr, map_pages, Frames, Sv39},
+        FrameAllocator, PageTableMut, VmFlags, PPN,
+    };
+    use core::ptr::NonNull;
+
+    /// 不能访问任何子页表的映射。
+    struct NoTable;
+
+    impl PhysMapper<Sv39> for NoTable {
+        fn map_frame(&self, _ppn: PPN<Sv39>) -> Option<NonNull<u8>> {
+            None
+        }
+
+        fn map_table(&self, _ppn: PPN<Sv39>, _pos: Pos<Sv39>) -> NonNull<Pte<Sv39>> {
+            panic!("shared table is visited")
+        }
+    }
+
+    let mut frames = Frames::<4>::new();
+    let roots = [
+        frames.allocate_one().unwrap(),
+        frames.allocate_one().unwrap(),
+    ];
+    let mut a = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[0])) };
+    let mut b = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(roots[1])) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b0011) };
+    map_pages(&mut a, &mut frames, &[(0x200..0x201, 0x8000, flags, 0)]);
+    map_pages(
+        &mut b,
+        &mut frames,
+        &[(1 << 18..2 << 18, 1 << 18, flags, 2)],
+    );
+    // 两个根页表指向同一个子页表
+    b[0] = a[0];
+
+    let added = Change {
+        range: VPN::new(1 << 18)..VPN::new(2 << 18),
+        kind: ChangeKind::Added,
+    };
+    assert!(diff(&a.as_ref(), &b.as_ref(), NoTable).unwrap().eq([added]));
+}
@@ -1,20 +1,18 @@
 ﻿use super::{Pos, Visitor};
-use crate::{PageTable, Pte, VmMeta, PPN};
-use core::{fmt, marker::PhantomData, ptr::NonNull};
+use crate::{PageTableRef, PhysMapper, Pte, VAddr, VmMeta, PPN};
+use core::{fmt, marker::PhantomData};
 
 /// 页表格式化器。
 ///
 /// 为了遍历，需要知道在当前地址空间访问物理页的方法。
-pub struct PageTableFormatter<Meta: VmMeta, F: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> {
+pub struct PageTableFormatter<'a, Meta: VmMeta, M: PhysMapper<Meta>> {
     /// 根页表。
-    pub pt: PageTable<Meta>,
-    /// 物理页转换为指针。
-    pub f: F,
+    pub pt: PageTableRef<'a, Meta>,
+    /// 访问物理页的方式。
+    pub mapper: M,
 }
 
-impl<Meta: VmMeta, F: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> fmt::Debug
-    for PageTableFormatter<Meta, F>
-{
+impl<Meta: VmMeta, M: PhysMapper<Meta>> fmt::Debug for PageTableFormatter<'_, Meta, M> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.pt.walk(
             Pos {
@@ -23,7 +21,7 @@ impl<Meta: VmMeta, F: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> fmt::Debug
             },
             &mut FmtVisitor {
                 f,
-                t: &self.f,
+                t: &self.mapper,
                 max_level: self.pt.level,
                 level: self.pt.level + 1,
                 _phantom: PhantomData,
@@ -33,7 +31,41 @@ impl<Meta: VmMeta, F: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> fmt::Debug
     }
 }
 
-struct FmtVisitor<'f1, 'f2, Meta: VmMeta, T: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> {
+/// 紧凑的页表格式化器。
+///
+/// 每行打印一个 [`Region`](super::Region)，而不是每个页表项一行。
+pub struct RegionFormatter<'a, Meta: VmMeta, M: PhysMapper<Meta>> {
+    /// 根页表。
+    pub pt: PageTableRef<'a, Meta>,
+    /// 访问物理页的方式。
+    pub mapper: M,
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> fmt::Debug for RegionFormatter<'_, Meta, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for region in self.pt.regions(&self.mapper) {
+            // 打印最后一个字节的地址，避免地址空间顶端的区域回绕
+            let last = VAddr::<Meta>::new((region.vpn_range.end.val() << Meta::PAGE_BITS) - 1);
+            write!(
+                f,
+                "{:#018x}..={:#018x} -> {:#018x} (",
+                region.vpn_range.start.base().val(),
+                last.val(),
+                region.ppn_start.base().val(),
+            )?;
+            // 区域的属性取自第一个叶子页表项，按它的级别打印
+            let level = self
+                .pt
+                .translate(region.vpn_range.start.base(), &self.mapper)
+                .map_or(0, |(_, _, level)| level);
+            Meta::fmt_leaf_flags(f, region.flags.val(), level)?;
+            writeln!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+struct FmtVisitor<'f1, 'f2, Meta: VmMeta, T: PhysMapper<Meta>> {
     f: &'f1 mut fmt::Formatter<'f2>,
     t: T,
     max_level: usize,
@@ -41,7 +73,7 @@ struct FmtVisitor<'f1, 'f2, Meta: VmMeta, T: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>
     _phantom: PhantomData<Meta>,
 }
 
-impl<'f1, 'f2, Meta: VmMeta, T: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> FmtVisitor<'f1, 'f2, Meta, T> {
+impl<'f1, 'f2, Meta: VmMeta, T: PhysMapper<Meta>> FmtVisitor<'f1, 'f2, Meta, T> {
     /// 打印一个物理页号。
     fn ppn(&mut self, ppn: PPN<Meta>, level: usize) {
         if level >= self.level {
@@ -67,32 +99,30 @@ impl<'f1, 'f2, Meta: VmMeta, T: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> FmtVisitor<
             range.end.val()
         )
         .unwrap();
-        Meta::fmt_flags(self.f, pte.flags().val()).unwrap();
+        Meta::fmt_leaf_flags(self.f, pte.leaf_flags(pos.level).val(), pos.level).unwrap();
         write!(self.f, ")").unwrap();
         self.level = pos.level;
     }
 }
 
-impl<'f1, 'f2, Meta: VmMeta, T: Fn(PPN<Meta>) -> NonNull<Pte<Meta>>> Visitor<Meta>
-    for FmtVisitor<'f1, 'f2, Meta, T>
-{
+impl<'f1, 'f2, Meta: VmMeta, T: PhysMapper<Meta>> Visitor<Meta> for FmtVisitor<'f1, 'f2, Meta, T> {
     #[inline]
     fn meet(
         &mut self,
         level: usize,
         pte: Pte<Meta>,
-        _target: Pos<Meta>,
+        target: Pos<Meta>,
     ) -> Option<core::ptr::NonNull<Pte<Meta>>> {
         self.ppn(pte.ppn(), level);
         write!(self.f, " - ").unwrap();
         self.level = level;
-        Some((self.t)(pte.ppn()))
+        Some(self.t.map_table(pte.ppn(), Pos::new(target.vpn, level - 1)))
     }
 
     #[inline]
     fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
         if pte.is_valid() {
-            self.ppn(pte.ppn(), level);
+            self.ppn(pte.leaf_ppn(level), level);
             // 打印一些横线
             for _ in 0..level {
                 write!(self.f, " - ").unwrap();
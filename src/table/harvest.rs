@@ -0,0 +1,200 @@
+﻿use super::{map_child, skip, Decorator, Pos, Update};
+use crate::{PageTableError, PageTableMut, PhysMapper, Pte, RawPte, VmMeta, PPN, VPN};
+use core::{ops::Range, ptr::NonNull};
+
+/// 需要刷新 TLB 的虚页集合。
+///
+/// 最多记录 `N` 个虚页，超过容量时改为要求刷新整个地址空间。
+pub struct FlushSet<Meta: VmMeta, const N: usize> {
+    pages: [VPN<Meta>; N],
+    len: usize,
+    overflow: bool,
+}
+
+impl<Meta: VmMeta, const N: usize> FlushSet<Meta, N> {
+    /// 新建空的集合。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            pages: [VPN::ZERO; N],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// 需要刷新的虚页。大页只记录其起始虚页号。
+    ///
+    /// [`needs_full_flush`](Self::needs_full_flush) 为 `true` 时，这里只是需要刷新的虚页的一部分。
+    #[inline]
+    pub fn pages(&self) -> &[VPN<Meta>] {
+        &self.pages[..self.len]
+    }
+
+    /// 如果记录的虚页超过了容量，需要刷新整个地址空间，返回 `true`。
+    #[inline]
+    pub const fn needs_full_flush(&self) -> bool {
+        self.overflow
+    }
+
+    /// 如果不需要刷新任何虚页，返回 `true`。
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0 && !self.overflow
+    }
+
+    /// 记录需要刷新的虚页 `vpn`。
+    #[inline]
+    pub fn push(&mut self, vpn: VPN<Meta>) {
+        if self.len < N {
+            self.pages[self.len] = vpn;
+            self.len += 1;
+        } else {
+            self.overflow = true;
+        }
+    }
+}
+
+impl<Meta: VmMeta, const N: usize> Default for FlushSet<Meta, N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 扫描 `range` 范围，读取并清除所有叶子页表项的访问位。
+    ///
+    /// 访问位由 [`MmuMeta::ACCESSED_FLAG`](crate::MmuMeta::ACCESSED_FLAG) 给出，原子地清除，不会丢失硬件同时设置的其他位。
+    /// 每个有效的叶子页表项以其起始虚页号、物理页号、级别和清除前是否被访问报告给 `harvested`。
+    /// 与 `range` 相交的大页整体处理，共享的页表项（见 [`share`](Self::share)）被跳过。页表通过 `mapper` 访问。
+    ///
+    /// 返回清除了访问位、需要刷新 TLB 的虚页集合。
+    /// 如果方案没有访问位，或者目标平台不支持页表项宽度的原子操作，返回 [`PageTableError::Unsupported`]。
+    pub fn harvest_accessed<const N: usize>(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        mapper: &impl PhysMapper<Meta>,
+        harvested: impl FnMut(VPN<Meta>, PPN<Meta>, usize, bool),
+    ) -> Result<FlushSet<Meta, N>, PageTableError> {
+        if Meta::ACCESSED_FLAG == Meta::Raw::ZERO {
+            return Err(PageTableError::Unsupported);
+        }
+        self.check_range(&range)?;
+        let mut visitor = HarvestVisitor {
+            end: range.end,
+            mapper,
+            harvested,
+            flush: FlushSet::new(),
+            ans: Ok(()),
+        };
+        if range.start < range.end {
+            self.walk_mut(Pos::new(range.start, 0), &mut visitor);
+        }
+        visitor.ans.map(|()| visitor.flush)
+    }
+}
+
+struct HarvestVisitor<'a, Meta: VmMeta, M, H, const N: usize> {
+    end: VPN<Meta>,
+    mapper: &'a M,
+    harvested: H,
+    flush: FlushSet<Meta, N>,
+    ans: Result<(), PageTableError>,
+}
+
+impl<Meta, M, H, const N: usize> Decorator<Meta> for HarvestVisitor<'_, Meta, M, H, N>
+where
+    Meta: VmMeta,
+    M: PhysMapper<Meta>,
+    H: FnMut(VPN<Meta>, PPN<Meta>, usize, bool),
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            // 页表项与其存储类型布局相同
+            let ptr = (pte as *mut Pte<Meta>).cast::<Meta::Raw>();
+            let Some(old) = (unsafe { Meta::Raw::fetch_and(ptr, !Meta::ACCESSED_FLAG) }) else {
+                self.ans = Err(PageTableError::Unsupported);
+                return Pos::stop();
+            };
+            let accessed = old & Meta::ACCESSED_FLAG != Meta::Raw::ZERO;
+            if accessed {
+                self.flush.push(target.vpn);
+            }
+            (self.harvested)(
+                target.vpn,
+                pte.leaf_ppn(target.level),
+                target.level,
+                accessed,
+            );
+        }
+        skip(target.vpn, target.level, self.end, 0)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        // 大页转为访问大页页表项本身
+        Update::Target(if pte.is_valid() && !pte.is_shared() {
+            Pos::new(target.vpn.floor(level), level)
+        } else {
+            skip(target.vpn, level, self.end, 0)
+        })
+    }
+}
+
+#[test]
+fn test_harvest_accessed() {
+    use crate::{
+        test_meta::{map_pages, root_table, Frames, Sv39},
+        IdentityMapper, VmFlags,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let cold = unsafe { VmFlags::<Sv39>::from_raw(0b0000_0011) };
+    let hot = unsafe { VmFlags::<Sv39>::from_raw(0b0100_0011) };
+    map_pages(
+        &mut pt,
+        &mut frames,
+        &[
+            (0x200..0x201, 0x8200, hot, 0),
+            (0x201..0x202, 0x8201, cold, 0),
+            (0x202..0x203, 0x8202, hot, 0),
+            (0x400..0x600, 0x8400, hot, 1),
+        ],
+    );
+
+    let mut hot_pages = 0;
+    let flush = pt
+        .harvest_accessed::<2>(
+            VPN::new(0x200)..VPN::new(0x401),
+            &IdentityMapper,
+            |vpn, ppn, _, accessed| {
+                assert_eq!(ppn.val(), 0x8000 + vpn.val());
+                hot_pages += accessed as usize;
+            },
+        )
+        .unwrap();
+    assert_eq!(hot_pages, 3);
+    assert_eq!(flush.pages(), [VPN::new(0x200), VPN::new(0x202)]);
+    assert!(flush.needs_full_flush());
+
+    let flush = pt
+        .harvest_accessed::<2>(
+            VPN::new(0x200)..VPN::new(0x600),
+            &IdentityMapper,
+            |_, _, _, accessed| assert!(!accessed),
+        )
+        .unwrap();
+    assert!(flush.is_empty());
+    assert!(pt.mappings(&IdentityMapper).all(|m| m.flags == cold));
+}
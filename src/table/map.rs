@@ -0,0 +1,314 @@
+﻿use super::{check_leaf, map_child, zero_table, Decorator, Pos, Update};
+use crate::{
+    FrameAllocator, PageTableError, PageTableMut, PhysMapper, Pte, VmFlags, VmMeta, PPN, VPN,
+};
+use core::{marker::PhantomData, ops::Range, ptr::NonNull};
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 将 `range` 范围的虚页以 `level` 级页映射到从 `ppn` 开始的连续物理页，页表项具有 `flags` 属性。
+    ///
+    /// 缺少的中间页表从 `alloc` 分配，页表通过 `mapper` 访问。
+    /// 如果 `mapper` 不能直接访问物理页，`alloc` 分配的页帧必须已经清零。
+    /// 如果 `flags` 不是有效的叶子页表项属性，返回 [`PageTableError::InvalidFlags`]。
+    /// 遇到已经存在的映射时返回错误，已经建立的映射不会撤销。
+    pub fn map(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        ppn: PPN<Meta>,
+        flags: VmFlags<Meta>,
+        level: usize,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        if level > self.level || (level > 0 && !Meta::allow_huge(level)) {
+            return Err(PageTableError::InvalidLevel);
+        }
+        check_leaf(flags)?;
+        self.check_range(&range)?;
+        let align = Meta::pages_in_page(level);
+        if (range.start.val() | range.end.val() | ppn.val()) & (align - 1) != 0 {
+            return Err(PageTableError::Misaligned);
+        }
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let mut visitor = MapVisitor {
+            start: range.start,
+            end: range.end,
+            ppn,
+            flags: unsafe { VmFlags::from_raw(Meta::leaf_flags(flags.val(), level)) },
+            alloc,
+            mapper,
+            ans: Ok(()),
+            _phantom: PhantomData,
+        };
+        self.walk_mut(Pos::new(range.start, level), &mut visitor);
+        visitor.ans
+    }
+
+    /// 将 `range` 范围的虚页映射到从 `ppn` 开始的连续物理页，每一步都使用能用的最大的页。
+    ///
+    /// 页的级别由虚页号和物理页号的对齐、剩余的页数和 [`MmuMeta::allow_huge`](crate::MmuMeta::allow_huge) 共同决定。
+    /// 其他参数和出错时的行为见 [`map`](Self::map)。
+    pub fn map_auto(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        mut ppn: PPN<Meta>,
+        flags: VmFlags<Meta>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        self.check_range(&range)?;
+        let allow = |level: usize| level == 0 || Meta::allow_huge(level);
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let remain = range.end.val() - vpn.val();
+            let level = (0..=vpn.align_level().min(self.level))
+                .rev()
+                .find(|&level| {
+                    let pages = Meta::pages_in_page(level);
+                    allow(level) && ppn.val() & (pages - 1) == 0 && pages <= remain
+                })
+                .unwrap();
+            // 一直使用这一级的页，直到可能使用更大的页
+            let pages = Meta::pages_in_page(level);
+            let mut end = vpn + remain / pages * pages;
+            if let Some(up) = (level + 1..=self.level).find(|&level| allow(level)) {
+                let size = Meta::pages_in_page(up);
+                if (vpn.val() ^ ppn.val()) & (size - 1) == 0 {
+                    end = end.min(VPN::new((vpn.val() & !(size - 1)) + size));
+                }
+            }
+            self.map(vpn..end, ppn, flags, level, alloc, mapper)?;
+            ppn += end.val() - vpn.val();
+            vpn = end;
+        }
+        Ok(())
+    }
+}
+
+struct MapVisitor<'a, Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> {
+    start: VPN<Meta>,
+    end: VPN<Meta>,
+    ppn: PPN<Meta>,
+    flags: VmFlags<Meta>,
+    alloc: &'a mut A,
+    mapper: &'a M,
+    ans: Result<(), PageTableError>,
+    _phantom: PhantomData<Meta>,
+}
+
+impl<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> MapVisitor<'_, Meta, A, M> {
+    #[inline]
+    fn fail(&mut self, e: PageTableError) -> Pos<Meta> {
+        self.ans = Err(e);
+        Pos::stop()
+    }
+}
+
+impl<Meta: VmMeta, A: FrameAllocator<Meta>, M: PhysMapper<Meta>> Decorator<Meta>
+    for MapVisitor<'_, Meta, A, M>
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            return self.fail(PageTableError::AlreadyMapped);
+        }
+        *pte = self
+            .flags
+            .build_pte(self.ppn + (target.vpn.val() - self.start.val()));
+        let next = target.next();
+        if next.vpn < self.end {
+            next
+        } else {
+            Pos::stop()
+        }
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        if pte.is_shared() {
+            return Update::Target(self.fail(PageTableError::SharedTable));
+        }
+        if pte.is_valid() {
+            return Update::Target(self.fail(PageTableError::HugePageConflict));
+        }
+        let Some(ppn) = self.alloc.allocate_one() else {
+            return Update::Target(self.fail(PageTableError::OutOfFrames));
+        };
+        unsafe { zero_table(self.mapper, ppn, level - 1) };
+        let ptr = self.mapper.map_table(ppn, Pos::new(target.vpn, level - 1));
+        let flags = unsafe { VmFlags::<Meta>::from_raw(Meta::TABLE_FLAGS) };
+        Update::Pte(flags.build_pte(ppn), ptr)
+    }
+}
+
+#[test]
+fn test_map() {
+    use crate::{
+        test_meta::{frame_ptr, root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<5>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+
+    let range = VPN::new(0x200)..VPN::new(0x204);
+    assert_eq!(
+        pt.map(
+            range.clone(),
+            PPN::new(0x8000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Ok(())
+    );
+    assert_eq!(frames.used(), 3);
+    let pt1 = unsafe {
+        crate::PageTableRef::<Sv39>::from_raw_parts(frame_ptr(pt[0].ppn()), VPN::ZERO, 1)
+    };
+    assert!(pt1[1].is_valid() && !pt1[1].is_leaf());
+    let pt0 = unsafe {
+        crate::PageTableRef::<Sv39>::from_raw_parts(frame_ptr(pt1[1].ppn()), VPN::new(0x200), 0)
+    };
+    for i in 0..4 {
+        assert_eq!(pt0[i], flags.build_pte(PPN::new(0x8000 + i as u64)));
+    }
+    assert!(!pt0[4].is_valid());
+
+    assert_eq!(
+        pt.map(
+            range,
+            PPN::new(0x9000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Err(PageTableError::AlreadyMapped)
+    );
+    assert_eq!(
+        pt.map(
+            VPN::new(0x400)..VPN::new(0x600),
+            PPN::new(0x10000),
+            flags,
+            1,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Ok(())
+    );
+    assert_eq!(frames.used(), 3);
+    assert_eq!(
+        pt.map(
+            VPN::new(0x401)..VPN::new(0x402),
+            PPN::new(0x9000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Err(PageTableError::HugePageConflict)
+    );
+    assert_eq!(
+        pt.map(
+            VPN::new(0x600)..VPN::new(0x601),
+            PPN::new(0x9000),
+            flags,
+            1,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Err(PageTableError::Misaligned)
+    );
+    // 没有 V 位，或者没有 RWX 位
+    for raw in [0b1010, 0b0001] {
+        assert_eq!(
+            pt.map(
+                VPN::new(0x300)..VPN::new(0x301),
+                PPN::new(0x9000),
+                unsafe { VmFlags::from_raw(raw) },
+                0,
+                &mut frames,
+                &IdentityMapper
+            ),
+            Err(PageTableError::InvalidFlags)
+        );
+    }
+    assert_eq!(
+        pt.map(
+            VPN::new(1 << 18)..VPN::new((1 << 18) + 1),
+            PPN::new(0x9000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        pt.map(
+            VPN::new(2 << 18)..VPN::new((2 << 18) + 1),
+            PPN::new(0x9000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper
+        ),
+        Err(PageTableError::OutOfFrames)
+    );
+}
+
+#[test]
+fn test_map_auto() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+
+    // 1 GiB + 8 KiB
+    pt.map_auto(
+        VPN::new(0x4_0000)..VPN::new(0x8_0002),
+        PPN::new(0x4_0000),
+        flags,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    let mut levels = pt.mappings(&IdentityMapper).map(|m| (m.vpn.val(), m.level));
+    assert_eq!(levels.next(), Some((0x4_0000, 2)));
+    assert_eq!(levels.next(), Some((0x8_0000, 0)));
+    assert_eq!(levels.next(), Some((0x8_0001, 0)));
+    assert_eq!(levels.next(), None);
+
+    // 4 kiB 页直到 2 MiB 边界，然后是 2 MiB 页
+    pt.map_auto(
+        VPN::new(0x8_0002)..VPN::new(0x8_0600),
+        PPN::new(0x8_0002),
+        flags,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    let huge = pt
+        .mappings(&IdentityMapper)
+        .filter(|m| m.level == 1)
+        .count();
+    assert_eq!(huge, 2);
+    assert_eq!(pt.mappings(&IdentityMapper).count(), 3 + 0x1fe + 2);
+}
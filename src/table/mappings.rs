@@ -0,0 +1,155 @@
+﻿use super::{map_child, Pos, Visitor};
+use crate::{PageTableMut, PageTableRef, PhysMapper, Pte, VmFlags, VmMeta, PPN, VPN};
+use core::{marker::PhantomData, ptr::NonNull};
+
+/// 页表中的一个叶子映射。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Mapping<Meta: VmMeta> {
+    /// 映射的起始虚页号。
+    pub vpn: VPN<Meta>,
+    /// 映射到的起始物理页号。
+    pub ppn: PPN<Meta>,
+    /// 叶子页表项的属性。
+    pub flags: VmFlags<Meta>,
+    /// 叶子页表项的级别。
+    pub level: usize,
+}
+
+impl<'a, Meta: VmMeta> PageTableRef<'a, Meta> {
+    /// 按虚页号从小到大迭代页表中的所有叶子映射。
+    ///
+    /// 页表通过 `mapper` 访问。无效的页表项整体跳过，不会逐页访问。
+    #[inline]
+    pub fn mappings<M: PhysMapper<Meta>>(&self, mapper: M) -> Mappings<'a, Meta, M> {
+        Mappings {
+            pt: *self,
+            mapper,
+            next: Some(self.range().start),
+        }
+    }
+}
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 按虚页号从小到大迭代页表中的所有叶子映射。
+    ///
+    /// 见 [`PageTableRef::mappings`]。
+    #[inline]
+    pub fn mappings<M: PhysMapper<Meta>>(&self, mapper: M) -> Mappings<'_, Meta, M> {
+        self.as_ref().mappings(mapper)
+    }
+}
+
+/// 叶子映射迭代器。
+///
+/// 每次迭代从根页表查找下一个叶子映射，因此不需要额外的栈空间。
+pub struct Mappings<'a, Meta: VmMeta, M> {
+    pt: PageTableRef<'a, Meta>,
+    mapper: M,
+    next: Option<VPN<Meta>>,
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Iterator for Mappings<'_, Meta, M> {
+    type Item = Mapping<Meta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let end = self.pt.range().end;
+        let mut visitor = FindVisitor {
+            mapper: &self.mapper,
+            end,
+            ans: None,
+            _phantom: PhantomData,
+        };
+        self.pt.walk(Pos::new(self.next?, 0), &mut visitor);
+        let ans = visitor.ans;
+        self.next = ans
+            .map(|m| m.vpn + Meta::pages_in_page(m.level))
+            .filter(|vpn| *vpn < end);
+        ans
+    }
+}
+
+/// 查找第一个叶子映射的访问器。
+struct FindVisitor<'a, Meta: VmMeta, M> {
+    mapper: &'a M,
+    end: VPN<Meta>,
+    ans: Option<Mapping<Meta>>,
+    _phantom: PhantomData<Meta>,
+}
+
+impl<Meta: VmMeta, M> FindVisitor<'_, Meta, M> {
+    /// 找到叶子页表项，或跳过 `vpn` 所在的 `level` 级无效页表项。
+    #[inline]
+    fn found(&mut self, pte: Pte<Meta>, vpn: VPN<Meta>, level: usize) -> Pos<Meta> {
+        let vpn = vpn.floor(level);
+        if pte.is_valid() {
+            self.ans = Some(Mapping {
+                vpn,
+                ppn: pte.leaf_ppn(level),
+                flags: pte.leaf_flags(level),
+                level,
+            });
+            return Pos::stop();
+        }
+        let next = vpn + Meta::pages_in_page(level);
+        if next < self.end {
+            Pos::new(next, 0)
+        } else {
+            Pos::stop()
+        }
+    }
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Visitor<Meta> for FindVisitor<'_, Meta, M> {
+    #[inline]
+    fn arrive(&mut self, pte: Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        self.found(pte, target.vpn, target.level)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        self.found(pte, target.vpn, level)
+    }
+}
+
+#[test]
+fn test_mappings() {
+    use crate::{
+        test_meta::{map_pages, root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    map_pages(
+        &mut pt,
+        &mut frames,
+        &[
+            (0x200..0x202, 0x8000, flags, 0),
+            (0x4_0000..0x4_0200, 0x10000, flags, 1),
+        ],
+    );
+
+    let mut iter = pt.mappings(&IdentityMapper);
+    let mapping = |vpn, ppn, level| Mapping {
+        vpn: VPN::new(vpn),
+        ppn: PPN::new(ppn),
+        flags,
+        level,
+    };
+    assert_eq!(iter.next(), Some(mapping(0x200, 0x8000, 0)));
+    assert_eq!(iter.next(), Some(mapping(0x201, 0x8001, 0)));
+    assert_eq!(iter.next(), Some(mapping(0x4_0000, 0x10000, 1)));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next(), None);
+}
@@ -1,38 +1,82 @@
-﻿mod fmt;
+﻿mod collapse;
+mod cow;
+mod diff;
+mod fmt;
+mod harvest;
+mod map;
+mod mappings;
 mod pos;
+mod protect;
+mod regions;
+mod rmap;
+mod share;
+mod split;
+mod translate;
+mod unmap;
 mod visit;
 
-use crate::{Pte, VmMeta, VPN};
+use crate::{PageTableError, PhysMapper, Pte, RawPte, VmFlags, VmMeta, PPN, VPN};
 use core::{
     ops::{Index, IndexMut, Range},
     ptr::NonNull,
 };
 use visit::{walk_inner, walk_inner_mut};
 
-pub use fmt::PageTableFormatter;
+pub use diff::{diff, Change, ChangeKind, Diff};
+pub use fmt::{PageTableFormatter, RegionFormatter};
+pub use harvest::FlushSet;
+pub use mappings::{Mapping, Mappings};
 pub use pos::Pos;
+pub use regions::{Region, Regions};
 pub use visit::{Decorator, Update, Visitor};
 
-/// 页表。
+/// 只读页表。
 ///
 /// 不持有页表的所有权，因为页表总是在一些物理页帧上。
-pub struct PageTable<Meta: VmMeta> {
-    mem: &'static mut [Pte<Meta>],
+#[derive(Clone, Copy)]
+pub struct PageTableRef<'a, Meta: VmMeta> {
+    mem: &'a [Pte<Meta>],
     base: VPN<Meta>,
     level: usize,
 }
 
-impl<Meta: VmMeta> PageTable<Meta> {
+/// 可变页表。
+///
+/// 不持有页表的所有权，因为页表总是在一些物理页帧上。
+pub struct PageTableMut<'a, Meta: VmMeta> {
+    mem: &'a mut [Pte<Meta>],
+    base: VPN<Meta>,
+    level: usize,
+}
+
+impl<'a, Meta: VmMeta> PageTableRef<'a, Meta> {
+    /// 从页表项数组创建容纳 `base` 的 `level` 级页表。
+    ///
+    /// `level` 超过 `Meta::MAX_LEVEL`，或 `N` 不是 `level` 级页表的页表项数量时，返回 [`PageTableError::InvalidLevel`]。
+    #[inline]
+    pub fn new<const N: usize>(
+        mem: &'a [Pte<Meta>; N],
+        base: VPN<Meta>,
+        level: usize,
+    ) -> Result<Self, PageTableError> {
+        check_len::<Meta>(N, level)?;
+        Ok(Self {
+            mem,
+            base: base.floor(level),
+            level,
+        })
+    }
+
     /// 从指向第一个页表项的指针创建页表。
     ///
     /// # Safety
     ///
-    /// 同 [from_raw_parts_mut](core::slice::from_raw_parts_mut).
+    /// 同 [from_raw_parts](core::slice::from_raw_parts)，并且 `level` 不能超过 `Meta::MAX_LEVEL`。
     #[inline]
     pub unsafe fn from_raw_parts(ptr: NonNull<Pte<Meta>>, base: VPN<Meta>, level: usize) -> Self {
+        debug_assert!(level <= Meta::MAX_LEVEL);
         Self {
-            // 显然需要 level <= Meta::MAX_LEVEL
-            mem: core::slice::from_raw_parts_mut(ptr.as_ptr(), 1 << Meta::LEVEL_BITS[level]),
+            mem: core::slice::from_raw_parts(ptr.as_ptr(), 1 << Meta::LEVEL_BITS[level]),
             base: base.floor(level),
             level,
         }
@@ -42,7 +86,7 @@ impl<Meta: VmMeta> PageTable<Meta> {
     ///
     /// # Safety
     ///
-    /// 同 [from_raw_parts_mut](core::slice::from_raw_parts_mut).
+    /// 同 [from_raw_parts](core::slice::from_raw_parts).
     #[inline]
     pub unsafe fn from_root(root: NonNull<Pte<Meta>>) -> Self {
         Self::from_raw_parts(root, VPN::ZERO, Meta::MAX_LEVEL)
@@ -66,20 +110,256 @@ impl<Meta: VmMeta> PageTable<Meta> {
         self.base..self.base + Meta::pages_in_table(self.level)
     }
 
+    /// 如果页表中没有有效的页表项，返回 `true`。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.mem.iter().all(|pte| !pte.is_valid())
+    }
+
     /// 使用访问器 `visitor` 遍历页表。
     #[inline]
     pub fn walk(&self, mut target: Pos<Meta>, visitor: &mut impl Visitor<Meta>) {
         walk_inner(self, visitor, &mut target);
     }
+}
+
+impl<'a, Meta: VmMeta> PageTableMut<'a, Meta> {
+    /// 从页表项数组创建容纳 `base` 的 `level` 级页表。
+    ///
+    /// `level` 超过 `Meta::MAX_LEVEL`，或 `N` 不是 `level` 级页表的页表项数量时，返回 [`PageTableError::InvalidLevel`]。
+    #[inline]
+    pub fn new<const N: usize>(
+        mem: &'a mut [Pte<Meta>; N],
+        base: VPN<Meta>,
+        level: usize,
+    ) -> Result<Self, PageTableError> {
+        check_len::<Meta>(N, level)?;
+        Ok(Self {
+            mem,
+            base: base.floor(level),
+            level,
+        })
+    }
+
+    /// 从指向第一个页表项的指针创建页表。
+    ///
+    /// # Safety
+    ///
+    /// 同 [from_raw_parts_mut](core::slice::from_raw_parts_mut)，并且 `level` 不能超过 `Meta::MAX_LEVEL`。
+    #[inline]
+    pub unsafe fn from_raw_parts(ptr: NonNull<Pte<Meta>>, base: VPN<Meta>, level: usize) -> Self {
+        debug_assert!(level <= Meta::MAX_LEVEL);
+        Self {
+            mem: core::slice::from_raw_parts_mut(ptr.as_ptr(), 1 << Meta::LEVEL_BITS[level]),
+            base: base.floor(level),
+            level,
+        }
+    }
+
+    /// 从指向根页表的指针创建页表。
+    ///
+    /// # Safety
+    ///
+    /// 同 [from_raw_parts_mut](core::slice::from_raw_parts_mut).
+    #[inline]
+    pub unsafe fn from_root(root: NonNull<Pte<Meta>>) -> Self {
+        Self::from_raw_parts(root, VPN::ZERO, Meta::MAX_LEVEL)
+    }
+
+    /// 借出只读页表。
+    #[inline]
+    pub fn as_ref(&self) -> PageTableRef<'_, Meta> {
+        PageTableRef {
+            mem: self.mem,
+            base: self.base,
+            level: self.level,
+        }
+    }
+
+    /// 借出可变页表。
+    #[inline]
+    pub fn reborrow(&mut self) -> PageTableMut<'_, Meta> {
+        PageTableMut {
+            mem: self.mem,
+            base: self.base,
+            level: self.level,
+        }
+    }
+
+    /// 获取指向第一个页表项的指针。
+    #[inline]
+    pub fn as_ptr(&self) -> *const Pte<Meta> {
+        self.mem.as_ptr()
+    }
+
+    /// 获取指向第一个页表项的可变指针。
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut Pte<Meta> {
+        self.mem.as_mut_ptr()
+    }
+
+    /// 获取页表级别。
+    #[inline]
+    pub const fn level(&self) -> usize {
+        self.level
+    }
+
+    /// 获取页表容纳的虚页号范围。
+    #[inline]
+    pub fn range(&self) -> Range<VPN<Meta>> {
+        self.base..self.base + Meta::pages_in_table(self.level)
+    }
+
+    /// 如果页表中没有有效的页表项，返回 `true`。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.as_ref().is_empty()
+    }
+
+    /// 使用访问器 `visitor` 遍历页表。
+    #[inline]
+    pub fn walk(&self, target: Pos<Meta>, visitor: &mut impl Visitor<Meta>) {
+        self.as_ref().walk(target, visitor);
+    }
 
     /// 使用访问器 `visitor` 遍历并修改页表。
     #[inline]
     pub fn walk_mut(&mut self, mut target: Pos<Meta>, visitor: &mut impl Decorator<Meta>) {
         walk_inner_mut(self, visitor, &mut target);
     }
+
+    /// 检查 `range` 是否在页表容纳的虚页号范围内。
+    #[inline]
+    fn check_range(&self, range: &Range<VPN<Meta>>) -> Result<(), PageTableError> {
+        let table = self.range();
+        if range.start < table.start || range.end > table.end {
+            Err(PageTableError::AddressOutOfRange)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, Meta: VmMeta> From<PageTableMut<'a, Meta>> for PageTableRef<'a, Meta> {
+    #[inline]
+    fn from(value: PageTableMut<'a, Meta>) -> Self {
+        Self {
+            mem: value.mem,
+            base: value.base,
+            level: value.level,
+        }
+    }
 }
 
-impl<Meta: VmMeta> Index<usize> for PageTable<Meta> {
+/// 检查页表级别和页表项数量。
+#[inline]
+fn check_len<Meta: VmMeta>(len: usize, level: usize) -> Result<(), PageTableError> {
+    if level <= Meta::MAX_LEVEL && len == 1 << Meta::LEVEL_BITS[level] {
+        Ok(())
+    } else {
+        Err(PageTableError::InvalidLevel)
+    }
+}
+
+/// 检查 `flags` 是否是有效的叶子页表项属性。
+///
+/// 0 级页表项在有的方案中与表项形式相同，因此按 1 级叶子页表项检查。
+#[inline]
+fn check_leaf<Meta: VmMeta>(flags: VmFlags<Meta>) -> Result<(), PageTableError> {
+    let flags = Meta::leaf_flags(flags.val(), 1);
+    if Meta::is_valid(flags) && Meta::is_leaf(flags) {
+        Ok(())
+    } else {
+        Err(PageTableError::InvalidFlags)
+    }
+}
+
+/// 比较 `a_level` 级叶子属性 `a` 和 `b_level` 级叶子属性 `b`。
+///
+/// 有的方案中叶子页表项的形式随级别变化，因此较小的页按较大的页的形式比较。
+/// 较小的页带有较大的页特有的特性位时，这些位的含义不同，总是不相同。
+#[inline]
+fn same_leaf_flags<Meta: VmMeta>(
+    a: VmFlags<Meta>,
+    a_level: usize,
+    b: VmFlags<Meta>,
+    b_level: usize,
+) -> bool {
+    let (small, small_level, level) = if a_level < b_level {
+        (a, a_level, b_level)
+    } else {
+        (b, b_level, a_level)
+    };
+    small.val() & huge_only_flags::<Meta>(small_level, level) == Meta::Raw::ZERO
+        && Meta::leaf_flags(a.val(), level) == Meta::leaf_flags(b.val(), level)
+}
+
+/// `level` 级叶子页表项特有、`small_level` 级叶子页表项中没有或含义不同的特性位。
+///
+/// 例如 x86 大页的 PS 位是 0 级页表项的 PAT 位，大页的 PAT 位在 0 级页表项中是物理页号。
+#[inline]
+fn huge_only_flags<Meta: VmMeta>(small_level: usize, level: usize) -> Meta::Raw {
+    let flags = |level| Meta::leaf_flags(Meta::Raw::ZERO, level) | Meta::leaf_ppn_flags(level);
+    flags(level) & !flags(small_level)
+}
+
+/// 通过 `mapper` 访问 `level` 级页表项 `pte` 指向的子页表。
+///
+/// 大多数访问器的 [`meet`](Visitor::meet) 就是这样实现的。
+#[inline]
+pub(crate) fn map_child<Meta: VmMeta>(
+    mapper: &impl PhysMapper<Meta>,
+    level: usize,
+    pte: Pte<Meta>,
+    target: Pos<Meta>,
+) -> Option<NonNull<Pte<Meta>>> {
+    Some(mapper.map_table(pte.ppn(), Pos::new(target.vpn, level - 1)))
+}
+
+/// 跳过包含 `vpn` 的 `level` 级页，以其后的 `next_level` 级页表项为下一个目标。
+///
+/// 到达 `end` 时结束遍历。
+#[inline]
+fn skip<Meta: VmMeta>(
+    vpn: VPN<Meta>,
+    level: usize,
+    end: VPN<Meta>,
+    next_level: usize,
+) -> Pos<Meta> {
+    let next = vpn.floor(level) + Meta::pages_in_page(level);
+    if next < end {
+        Pos::new(next, next_level)
+    } else {
+        Pos::stop()
+    }
+}
+
+/// 如果 `mapper` 能直接访问物理页 `ppn`，将它作为 `level` 级页表清零并返回指针。
+///
+/// # Safety
+///
+/// `ppn` 必须是刚分配的页帧，没有被其他页表或数据使用。
+#[inline]
+pub(crate) unsafe fn zero_table<Meta: VmMeta>(
+    mapper: &impl PhysMapper<Meta>,
+    ppn: PPN<Meta>,
+    level: usize,
+) -> Option<NonNull<Pte<Meta>>> {
+    let ptr = mapper.map_frame(ppn)?.cast();
+    core::slice::from_raw_parts_mut(ptr.as_ptr(), 1 << Meta::LEVEL_BITS[level]).fill(Pte::ZERO);
+    Some(ptr)
+}
+
+impl<Meta: VmMeta> Index<usize> for PageTableRef<'_, Meta> {
+    type Output = Pte<Meta>;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.mem[index]
+    }
+}
+
+impl<Meta: VmMeta> Index<usize> for PageTableMut<'_, Meta> {
     type Output = Pte<Meta>;
 
     #[inline]
@@ -88,9 +368,35 @@ impl<Meta: VmMeta> Index<usize> for PageTable<Meta> {
     }
 }
 
-impl<Meta: VmMeta> IndexMut<usize> for PageTable<Meta> {
+impl<Meta: VmMeta> IndexMut<usize> for PageTableMut<'_, Meta> {
     #[inline]
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.mem[index]
     }
 }
+
+#[test]
+fn test_new() {
+    use crate::test_meta::Sv39;
+
+    let mut mem = [Pte::<Sv39>::ZERO; 512];
+    let mut pt = PageTableMut::new(&mut mem, VPN::new(0x40000), 1).unwrap();
+    assert_eq!(pt.range(), VPN::new(0x40000)..VPN::new(0x80000));
+    pt[3] = unsafe { crate::VmFlags::from_raw(1) }.build_pte(crate::PPN::new(0x80));
+    assert!(!pt.as_ref().is_empty());
+    assert_eq!(
+        PageTableRef::new(&mem, VPN::ZERO, 2).unwrap()[3]
+            .ppn()
+            .val(),
+        0x80
+    );
+}
+
+#[test]
+fn test_new_invalid_level() {
+    use crate::test_meta::Sv39;
+
+    let mem = [Pte::<Sv39>::ZERO; 512];
+    assert!(PageTableRef::new(&mem, VPN::ZERO, 3).is_err());
+    assert!(PageTableRef::new(&[Pte::<Sv39>::ZERO; 256], VPN::ZERO, 0).is_err());
+}
@@ -1,4 +1,4 @@
-﻿use crate::{VmMeta, VPN};
+﻿use crate::{PageTableError, VmMeta, VPN};
 use core::fmt;
 
 /// `Meta` 方案中页表上的一个位置。
@@ -27,52 +27,97 @@ impl<Meta: VmMeta> Pos<Meta> {
     }
 
     /// 向前移动一页。
+    ///
+    /// # Panics
+    ///
+    /// 虚页号溢出时 panic，见 [`try_prev`](Self::try_prev)。
     #[inline]
     pub fn prev(self) -> Self {
-        let delta = match self.level {
-            0 => 1,
-            n => Meta::pages_in_table(n - 1),
-        };
-        match self.vpn.val().checked_sub(delta) {
-            Some(vpn) => Self {
+        self.try_prev().expect("prev: vpn overflow")
+    }
+
+    /// 向后移动一页。
+    ///
+    /// # Panics
+    ///
+    /// 虚页号溢出时 panic，见 [`try_next`](Self::try_next)。
+    #[inline]
+    pub fn next(self) -> Self {
+        self.try_next().expect("next: vpn overflow")
+    }
+
+    /// 向上移动一页。
+    ///
+    /// # Panics
+    ///
+    /// 级别溢出时 panic，见 [`try_up`](Self::try_up)。
+    #[inline]
+    pub fn up(self) -> Self {
+        self.try_up().expect("up: level overflow")
+    }
+
+    /// 向下移动一页。
+    ///
+    /// # Panics
+    ///
+    /// 级别溢出时 panic，见 [`try_down`](Self::try_down)。
+    #[inline]
+    pub fn down(self) -> Self {
+        self.try_down().expect("down: level overflow")
+    }
+
+    /// 向前移动一页，虚页号溢出时返回 [`PageTableError::AddressOutOfRange`]。
+    #[inline]
+    pub fn try_prev(self) -> Result<Self, PageTableError> {
+        match self.vpn.val().checked_sub(self.delta()) {
+            Some(vpn) => Ok(Self {
                 vpn: VPN::new(vpn),
                 ..self
-            },
-            None => panic!("prev: vpn overflow"),
+            }),
+            None => Err(PageTableError::AddressOutOfRange),
         }
     }
 
-    /// 向后移动一页。
+    /// 向后移动一页，虚页号溢出时返回 [`PageTableError::AddressOutOfRange`]。
     #[inline]
-    pub fn next(self) -> Self {
-        let delta = match self.level {
-            0 => 1,
-            n => Meta::pages_in_table(n - 1),
-        };
-        match self.vpn.val().checked_add(delta) {
-            Some(vpn) => Self {
+    pub fn try_next(self) -> Result<Self, PageTableError> {
+        match self.vpn.val().checked_add(self.delta()) {
+            Some(vpn) => Ok(Self {
                 vpn: VPN::new(vpn),
                 ..self
-            },
-            None => panic!("next: vpn overflow"),
+            }),
+            None => Err(PageTableError::AddressOutOfRange),
         }
     }
 
-    /// 向上移动一页。
+    /// 向上移动一页，已经是最高级时返回 [`PageTableError::InvalidLevel`]。
     #[inline]
-    pub fn up(self) -> Self {
-        match self.level.checked_add(1) {
-            Some(level) => Self { level, ..self },
-            None => panic!("up: level overflow"),
+    pub fn try_up(self) -> Result<Self, PageTableError> {
+        if self.level < Meta::MAX_LEVEL {
+            Ok(Self {
+                level: self.level + 1,
+                ..self
+            })
+        } else {
+            Err(PageTableError::InvalidLevel)
         }
     }
 
-    /// 向下移动一页。
+    /// 向下移动一页，级别溢出时返回 [`PageTableError::InvalidLevel`]。
     #[inline]
-    pub fn down(self) -> Self {
+    pub fn try_down(self) -> Result<Self, PageTableError> {
         match self.level.checked_sub(1) {
-            Some(level) => Self { level, ..self },
-            None => panic!("down: level overflow"),
+            Some(level) => Ok(Self { level, ..self }),
+            None => Err(PageTableError::InvalidLevel),
+        }
+    }
+
+    /// 移动一页跨过的虚页数。
+    #[inline]
+    fn delta(self) -> u64 {
+        match self.level {
+            0 => 1,
+            n => Meta::pages_in_table(n - 1),
         }
     }
 }
@@ -87,3 +132,19 @@ impl<Meta: VmMeta> fmt::Debug for Pos<Meta> {
         )
     }
 }
+
+#[test]
+fn test_try_move() {
+    use crate::test_meta::Sv39;
+
+    let pos = Pos::<Sv39>::new(VPN::ZERO, 0);
+    assert_eq!(
+        pos.try_prev().err(),
+        Some(PageTableError::AddressOutOfRange)
+    );
+    assert_eq!(pos.try_down().err(), Some(PageTableError::InvalidLevel));
+    let pos = pos.try_up().unwrap().try_next().unwrap();
+    assert_eq!((pos.vpn.val(), pos.level), (512, 1));
+    let top = Pos::<Sv39>::new(VPN::ZERO, Sv39::MAX_LEVEL);
+    assert_eq!(top.try_up().err(), Some(PageTableError::InvalidLevel));
+}
@@ -0,0 +1,201 @@
+﻿use super::{check_leaf, map_child, skip, split::split_pte, Decorator, Pos, Update};
+use crate::{FrameAllocator, PageTableError, PageTableMut, PhysMapper, Pte, VmFlags, VmMeta, VPN};
+use core::{ops::Range, ptr::NonNull};
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 修改 `range` 范围内所有映射的属性，保持物理页号不变。
+    ///
+    /// 先清除 `clear` 中的位，再设置 `set` 中的位。无效的页表项保持不变。
+    /// 每个被修改的叶子页表项以其起始虚页号和级别报告给 `protected`，供调用者刷新 TLB。
+    ///
+    /// 如果 `range` 只覆盖了一个大页的一部分，将这个大页拆分为从 `alloc` 分配的下一级页表，见 [`split`](Self::split)。
+    /// 页表通过 `mapper` 访问。如果 `range` 与共享的页表项（见 [`share`](Self::share)）相交，返回 [`PageTableError::SharedTable`]。
+    /// 如果修改后的属性不是有效的叶子页表项属性，返回 [`PageTableError::InvalidFlags`]。
+    /// 出错时已经修改的属性不会恢复。
+    pub fn protect(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        set: VmFlags<Meta>,
+        clear: VmFlags<Meta>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        protected: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        self.check_range(&range)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let mut visitor = ProtectVisitor {
+            end: range.end,
+            set: set.val(),
+            clear: clear.val(),
+            alloc,
+            mapper,
+            protected,
+            ans: Ok(()),
+        };
+        self.walk_mut(Pos::new(range.start, 0), &mut visitor);
+        visitor.ans
+    }
+}
+
+struct ProtectVisitor<'a, Meta: VmMeta, A, M, P> {
+    end: VPN<Meta>,
+    set: Meta::Raw,
+    clear: Meta::Raw,
+    alloc: &'a mut A,
+    mapper: &'a M,
+    protected: P,
+    ans: Result<(), PageTableError>,
+}
+
+impl<Meta, A, M, P> Decorator<Meta> for ProtectVisitor<'_, Meta, A, M, P>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+    P: FnMut(VPN<Meta>, usize),
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            let flags = (pte.leaf_flags(target.level).val() & !self.clear) | self.set;
+            let flags = unsafe { VmFlags::from_raw(Meta::leaf_flags(flags, target.level)) };
+            if let Err(e) = check_leaf(flags) {
+                self.ans = Err(e);
+                return Pos::stop();
+            }
+            let new = flags.build_pte(pte.leaf_ppn(target.level));
+            if new != *pte {
+                *pte = new;
+                (self.protected)(target.vpn, target.level);
+            }
+        }
+        skip(target.vpn, target.level, self.end, 0)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        if !pte.is_valid() {
+            return Update::Target(skip(target.vpn, level, self.end, 0));
+        }
+        if pte.is_shared() {
+            self.ans = Err(PageTableError::SharedTable);
+            return Update::Target(Pos::stop());
+        }
+        // 大页完全在范围内就直接修改，否则拆分
+        let base = target.vpn.floor(level);
+        if base >= target.vpn && base + Meta::pages_in_page(level) <= self.end {
+            return Update::Target(Pos::new(base, level));
+        }
+        match split_pte(pte, level, target.vpn, self.alloc, self.mapper) {
+            Ok((pte, ptr)) => Update::Pte(pte, ptr),
+            Err(e) => {
+                self.ans = Err(e);
+                Update::Target(Pos::stop())
+            }
+        }
+    }
+}
+
+#[test]
+fn test_protect() {
+    use crate::{
+        test_meta::{map_pages, root_table, Frames, Sv39},
+        IdentityMapper, Mapping,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let rwx = unsafe { VmFlags::<Sv39>::from_raw(0b1111) };
+    let w = unsafe { VmFlags::<Sv39>::from_raw(0b0100) };
+    map_pages(&mut pt, &mut frames, &[(0x200..0x400, 0x8000, rwx, 1)]);
+
+    let mut count = 0;
+    pt.protect(
+        VPN::new(0x300)..VPN::new(0x302),
+        VmFlags::ZERO,
+        w,
+        &mut frames,
+        &IdentityMapper,
+        |vpn, level| {
+            assert_eq!(level, 0);
+            assert!((0x300..0x302).contains(&vpn.val()));
+            count += 1;
+        },
+    )
+    .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(frames.used(), 3);
+    // 清除 RWX 位后不再是叶子
+    assert_eq!(
+        pt.protect(
+            VPN::new(0x300)..VPN::new(0x301),
+            VmFlags::ZERO,
+            rwx,
+            &mut frames,
+            &IdentityMapper,
+            |_, _| unreachable!(),
+        ),
+        Err(PageTableError::InvalidFlags)
+    );
+
+    let mut iter = pt.mappings(&IdentityMapper);
+    let first = iter.next().unwrap();
+    assert_eq!((first.vpn.val(), first.ppn.val()), (0x200, 0x8000));
+    assert_eq!((first.flags, first.level), (rwx, 0));
+    let Mapping { ppn, flags, .. } = iter.nth(0xff).unwrap();
+    assert_eq!(ppn.val(), 0x8100);
+    assert_eq!(flags, unsafe { VmFlags::from_raw(0b1011) });
+    assert_eq!(iter.count(), 0xff);
+}
+
+#[test]
+fn test_protect_giant_page() {
+    use crate::{
+        test_meta::{map_pages, root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let rwx = unsafe { VmFlags::<Sv39>::from_raw(0b1111) };
+    let w = unsafe { VmFlags::<Sv39>::from_raw(0b0100) };
+    map_pages(
+        &mut pt,
+        &mut frames,
+        &[(0x4_0000..0x8_0000, 0x4_0000, rwx, 2)],
+    );
+
+    let mut count = 0;
+    pt.protect(
+        VPN::new(0x4_0000)..VPN::new(0x4_0002),
+        VmFlags::ZERO,
+        w,
+        &mut frames,
+        &IdentityMapper,
+        |vpn, level| {
+            assert_eq!((vpn.val(), level), (0x4_0000 + count, 0));
+            count += 1;
+        },
+    )
+    .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(frames.used(), 3);
+
+    let mut iter = pt.mappings(&IdentityMapper);
+    for vpn in [0x4_0000, 0x4_0001] {
+        let m = iter.next().unwrap();
+        assert_eq!((m.vpn.val(), m.ppn.val(), m.level), (vpn, vpn, 0));
+        assert_eq!(m.flags, unsafe { VmFlags::from_raw(0b1011) });
+    }
+    assert!(iter.all(|m| m.flags == rwx && m.ppn.val() == m.vpn.val()));
+}
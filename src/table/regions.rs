@@ -0,0 +1,120 @@
+﻿use super::{same_leaf_flags, Mapping, Mappings};
+use crate::{PageTableMut, PageTableRef, PhysMapper, VmFlags, VmMeta, PPN, VPN};
+use core::ops::Range;
+
+/// 虚地址连续、物理地址连续且属性相同的一段映射。
+///
+/// 区域以页号而不是地址表示，因此字段是 `vpn_range` 和 `ppn_start` 而不是 `vaddr_range` 和 `paddr_start`：
+/// 位于地址空间顶端的区域的结束地址超出地址范围，以地址表示会回绕到 0。
+/// 需要地址时使用 [`VPN::base`] 和 [`PPN::base`]。
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Region<Meta: VmMeta> {
+    /// 映射的虚页号范围。
+    pub vpn_range: Range<VPN<Meta>>,
+    /// 映射到的起始物理页号。
+    pub ppn_start: PPN<Meta>,
+    /// 第一个叶子页表项的属性。
+    pub flags: VmFlags<Meta>,
+}
+
+impl<'a, Meta: VmMeta> PageTableRef<'a, Meta> {
+    /// 按虚地址从小到大迭代页表中的映射区域。
+    ///
+    /// 相邻的叶子映射如果属性相同且物理地址连续，合并为一个区域。页表通过 `mapper` 访问。
+    /// 不同级别的叶子属性按较大的页的形式比较。
+    #[inline]
+    pub fn regions<M: PhysMapper<Meta>>(&self, mapper: M) -> Regions<'a, Meta, M> {
+        Regions {
+            mappings: self.mappings(mapper),
+            pending: None,
+        }
+    }
+}
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 按虚地址从小到大迭代页表中的映射区域。
+    ///
+    /// 见 [`PageTableRef::regions`]。
+    #[inline]
+    pub fn regions<M: PhysMapper<Meta>>(&self, mapper: M) -> Regions<'_, Meta, M> {
+        self.as_ref().regions(mapper)
+    }
+}
+
+/// 映射区域迭代器。
+pub struct Regions<'a, Meta: VmMeta, M> {
+    mappings: Mappings<'a, Meta, M>,
+    /// 已经取出但不属于上一个区域的叶子映射。
+    pending: Option<Mapping<Meta>>,
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Iterator for Regions<'_, Meta, M> {
+    type Item = Region<Meta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.pending.take().or_else(|| self.mappings.next())?;
+        let mut pages = Meta::pages_in_page(first.level);
+        for next in self.mappings.by_ref() {
+            if same_leaf_flags(next.flags, next.level, first.flags, first.level)
+                && next.vpn.val() == first.vpn.val() + pages
+                && next.ppn.val() == first.ppn.val() + pages
+            {
+                pages += Meta::pages_in_page(next.level);
+            } else {
+                self.pending = Some(next);
+                break;
+            }
+        }
+        Some(Region {
+            vpn_range: first.vpn..first.vpn + pages,
+            ppn_start: first.ppn,
+            flags: first.flags,
+        })
+    }
+}
+
+#[test]
+fn test_regions() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<6>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let rx = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    let rw = unsafe { VmFlags::<Sv39>::from_raw(0b0111) };
+    let mut map = |start: u64, end: u64, ppn: u64, flags, level| {
+        pt.map(
+            VPN::new(start)..VPN::new(end),
+            PPN::new(ppn),
+            flags,
+            level,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap()
+    };
+    // 4 kiB 页与其后的 2 MiB 页物理地址连续
+    map(0x1fe, 0x200, 0x81fe, rx, 0);
+    map(0x200, 0x400, 0x8200, rx, 1);
+    // 属性不同
+    map(0x400, 0x402, 0x8400, rw, 0);
+    // 物理地址不连续
+    map(0x402, 0x403, 0x9000, rw, 0);
+    // 地址空间的最后一页
+    let last = (1 << 27) - 1;
+    map(last, last + 1, 0xa000, rw, 0);
+
+    let region = |start: u64, end: u64, ppn: u64, flags| Region {
+        vpn_range: VPN::new(start)..VPN::new(end),
+        ppn_start: PPN::new(ppn),
+        flags,
+    };
+    assert!(pt.regions(&IdentityMapper).eq([
+        region(0x1fe, 0x400, 0x81fe, rx),
+        region(0x400, 0x402, 0x8400, rw),
+        region(0x402, 0x403, 0x9000, rw),
+        region(last, last + 1, 0xa000, rw),
+    ]));
+}
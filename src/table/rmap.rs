@@ -0,0 +1,87 @@
+﻿use super::{map_child, Pos, Visitor};
+use crate::{PageTableMut, PageTableRef, PhysMapper, Pte, VmFlags, VmMeta, PPN, VPN};
+use core::ptr::NonNull;
+
+impl<Meta: VmMeta> PageTableRef<'_, Meta> {
+    /// 扫描页表，查找所有映射到物理页 `ppn` 的虚页。
+    ///
+    /// 每个找到的虚页以其虚页号、所在叶子页表项的级别和属性报告给 `found`，按虚页号从小到大。
+    /// 大页中只报告映射到 `ppn` 的那一个虚页。页表通过 `mapper` 访问。
+    pub fn reverse_lookup(
+        &self,
+        ppn: PPN<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        found: impl FnMut(VPN<Meta>, usize, VmFlags<Meta>),
+    ) {
+        self.walk(
+            Pos::new(self.range().start, 0),
+            &mut ReverseVisitor { ppn, mapper, found },
+        );
+    }
+}
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 扫描页表，查找所有映射到物理页 `ppn` 的虚页。
+    ///
+    /// 见 [`PageTableRef::reverse_lookup`]。
+    #[inline]
+    pub fn reverse_lookup(
+        &self,
+        ppn: PPN<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        found: impl FnMut(VPN<Meta>, usize, VmFlags<Meta>),
+    ) {
+        self.as_ref().reverse_lookup(ppn, mapper, found);
+    }
+}
+
+struct ReverseVisitor<'a, Meta: VmMeta, M, F> {
+    ppn: PPN<Meta>,
+    mapper: &'a M,
+    found: F,
+}
+
+impl<Meta, M, F> ReverseVisitor<'_, Meta, M, F>
+where
+    Meta: VmMeta,
+    F: FnMut(VPN<Meta>, usize, VmFlags<Meta>),
+{
+    /// 检查从 `vpn` 开始的 `level` 级叶子页表项 `pte` 是否映射到目标物理页。
+    #[inline]
+    fn check(&mut self, vpn: VPN<Meta>, pte: Pte<Meta>, level: usize) {
+        let offset = self.ppn.val().wrapping_sub(pte.leaf_ppn(level).val());
+        if pte.is_valid() && offset < Meta::pages_in_page(level) {
+            (self.found)(vpn + offset, level, pte.leaf_flags(level));
+        }
+    }
+}
+
+impl<Meta, M, F> Visitor<Meta> for ReverseVisitor<'_, Meta, M, F>
+where
+    Meta: VmMeta,
+    M: PhysMapper<Meta>,
+    F: FnMut(VPN<Meta>, usize, VmFlags<Meta>),
+{
+    #[inline]
+    fn arrive(&mut self, pte: Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        self.check(target.vpn, pte, 0);
+        target.next()
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        let base = target.vpn.floor(level);
+        self.check(base, pte, level);
+        Pos::new(base + Meta::pages_in_page(level), 0)
+    }
+}
@@ -0,0 +1,57 @@
+﻿use crate::{PageTableError, PageTableMut, PageTableRef, RawPte, VmFlags, VmMeta, VPN};
+use core::ops::Range;
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 将 `template` 中 `range` 范围的页表项以共享的方式链接到 `self`。
+    ///
+    /// `self` 和 `template` 必须覆盖相同的范围，`range` 必须按 `self` 的页表项对齐。
+    /// 链接的页表项带有 [`MmuMeta::SHARED_FLAG`](crate::MmuMeta::SHARED_FLAG)，指向的子页表和物理页仍属于 `template`：
+    /// 修改页表的操作不会进入共享的子页表，[`unmap`](Self::unmap) 只断开链接而不回收它们。
+    ///
+    /// `template` 中无效的页表项被跳过，已经链接的页表项保持不变，
+    /// 因此可以重复调用，将 `template` 中新增的页表项传播到 `self`。
+    /// 如果 `self` 中对应的页表项已经有效但不是同一个共享页表项，返回 [`PageTableError::AlreadyMapped`]，此时 `self` 不被修改。
+    pub fn share(
+        &mut self,
+        template: &PageTableRef<Meta>,
+        range: Range<VPN<Meta>>,
+    ) -> Result<(), PageTableError> {
+        if Meta::SHARED_FLAG == Meta::Raw::ZERO {
+            return Err(PageTableError::Unsupported);
+        }
+        if template.level != self.level {
+            return Err(PageTableError::InvalidLevel);
+        }
+        if template.base != self.base {
+            return Err(PageTableError::AddressOutOfRange);
+        }
+        self.check_range(&range)?;
+        let align = Meta::pages_in_page(self.level);
+        if (range.start.val() | range.end.val()) & (align - 1) != 0 {
+            return Err(PageTableError::Misaligned);
+        }
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let start = range.start.index_in(self.level);
+        let end = start + ((range.end.val() - range.start.val()) / align) as usize;
+        let shared = |i: usize| {
+            let pte = template.mem[i];
+            let flags = unsafe { VmFlags::<Meta>::from_raw(pte.flags().val() | Meta::SHARED_FLAG) };
+            flags.build_pte(pte.ppn())
+        };
+        // 先检查再链接，出错时不修改
+        for i in start..end {
+            let pte = self.mem[i];
+            if template.mem[i].is_valid() && pte.is_valid() && pte != shared(i) {
+                return Err(PageTableError::AlreadyMapped);
+            }
+        }
+        for i in start..end {
+            if template.mem[i].is_valid() {
+                self.mem[i] = shared(i);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+﻿use super::{huge_only_flags, map_child, Decorator, Pos, Update};
+use crate::{FrameAllocator, PageTableError, PageTableMut, PhysMapper, Pte, VmFlags, VmMeta, VPN};
+use core::{marker::PhantomData, ptr::NonNull};
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 将 `vpn` 所在的 `level` 级大页拆分为一个 `level - 1` 级页表。
+    ///
+    /// 新页表从 `alloc` 分配，其中每个页表项继承大页的属性，并指向大页中连续的物理页。页表通过 `mapper` 访问。
+    /// 新页表在链接前写入，如果 `mapper` 不能直接访问物理页，返回 [`PageTableError::Unsupported`]。
+    ///
+    /// 如果 `vpn` 所在的 `level` 级页表项已经指向子页表，什么也不做。
+    /// 如果 `vpn` 位于更高级的大页中，返回 [`PageTableError::HugePageConflict`]；
+    /// 位于共享的页表项（见 [`share`](Self::share)）中，返回 [`PageTableError::SharedTable`]。
+    pub fn split(
+        &mut self,
+        vpn: VPN<Meta>,
+        level: usize,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        if level == 0 || level > self.level {
+            return Err(PageTableError::InvalidLevel);
+        }
+        if !self.range().contains(&vpn) {
+            return Err(PageTableError::AddressOutOfRange);
+        }
+        let mut visitor = SplitVisitor {
+            alloc,
+            mapper,
+            ans: Ok(()),
+            _phantom: PhantomData,
+        };
+        self.walk_mut(Pos::new(vpn, level), &mut visitor);
+        visitor.ans
+    }
+}
+
+struct SplitVisitor<'a, Meta: VmMeta, A, M> {
+    alloc: &'a mut A,
+    mapper: &'a M,
+    ans: Result<(), PageTableError>,
+    _phantom: PhantomData<Meta>,
+}
+
+impl<Meta, A, M> Decorator<Meta> for SplitVisitor<'_, Meta, A, M>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        if !pte.is_valid() {
+            self.ans = Err(PageTableError::NotMapped);
+        } else if pte.is_leaf() {
+            match split_pte(*pte, target.level, target.vpn, self.alloc, self.mapper) {
+                Ok((new, _)) => *pte = new,
+                Err(e) => self.ans = Err(e),
+            }
+        }
+        Pos::stop()
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, _level: usize, pte: Pte<Meta>, _target: Pos<Meta>) -> Update<Meta> {
+        self.ans = Err(if pte.is_shared() {
+            PageTableError::SharedTable
+        } else if pte.is_valid() {
+            PageTableError::HugePageConflict
+        } else {
+            PageTableError::NotMapped
+        });
+        Update::Target(Pos::stop())
+    }
+}
+
+/// 为 `vpn` 所在的 `level` 级大页 `pte` 分配并写入 `level - 1` 级页表。
+///
+/// 新页表的每个页表项继承大页的属性，并指向大页中连续的物理页。
+/// 新页表在链接到父页表之前写入，因此 `mapper` 必须能直接访问物理页，否则返回 [`PageTableError::Unsupported`]。
+///
+/// 返回指向新页表的页表项和新页表的指针。
+pub(super) fn split_pte<Meta: VmMeta>(
+    pte: Pte<Meta>,
+    level: usize,
+    vpn: VPN<Meta>,
+    alloc: &mut impl FrameAllocator<Meta>,
+    mapper: &impl PhysMapper<Meta>,
+) -> Result<(Pte<Meta>, NonNull<Pte<Meta>>), PageTableError> {
+    let ppn = alloc.allocate_one().ok_or(PageTableError::OutOfFrames)?;
+    let Some(ptr) = mapper.map_frame(ppn) else {
+        alloc.deallocate_one(ppn);
+        return Err(PageTableError::Unsupported);
+    };
+    let len = 1 << Meta::LEVEL_BITS[level - 1];
+    let table = unsafe { core::slice::from_raw_parts_mut(ptr.cast::<Pte<Meta>>().as_ptr(), len) };
+    // 大页特有的特性位在下一级页表项中可能有其他含义（例如 x86 的 PAT），拆分时清除
+    let flags = pte.leaf_flags(level).val() & !huge_only_flags::<Meta>(level - 1, level);
+    let flags = unsafe { VmFlags::<Meta>::from_raw(Meta::leaf_flags(flags, level - 1)) };
+    let pages = Meta::pages_in_page(level - 1);
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = flags.build_pte(pte.leaf_ppn(level) + i as u64 * pages);
+    }
+    let flags = unsafe { VmFlags::<Meta>::from_raw(Meta::TABLE_FLAGS) };
+    Ok((
+        flags.build_pte(ppn),
+        mapper.map_table(ppn, Pos::new(vpn, level - 1)),
+    ))
+}
+
+#[test]
+fn test_split() {
+    use crate::{
+        test_meta::{map_pages, root_table, Frames, Sv39},
+        IdentityMapper,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    map_pages(&mut pt, &mut frames, &[(0x200..0x400, 0x8000, flags, 1)]);
+
+    let vpn = VPN::new(0x234);
+    assert_eq!(pt.split(vpn, 2, &mut frames, &IdentityMapper), Ok(()));
+    assert_eq!(
+        pt.split(VPN::new(0x400), 1, &mut frames, &IdentityMapper),
+        Err(PageTableError::NotMapped)
+    );
+    assert_eq!(pt.split(vpn, 1, &mut frames, &IdentityMapper), Ok(()));
+    assert_eq!(frames.used(), 3);
+    assert_eq!(pt.split(vpn, 1, &mut frames, &IdentityMapper), Ok(()));
+    assert_eq!(frames.used(), 3);
+
+    assert!(pt
+        .mappings(&IdentityMapper)
+        .all(|m| m.level == 0 && m.flags == flags && m.ppn.val() - m.vpn.val() == 0x7e00));
+    assert_eq!(pt.mappings(&IdentityMapper).count(), 512);
+}
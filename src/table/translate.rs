@@ -0,0 +1,114 @@
+﻿use super::{map_child, Pos, Visitor};
+use crate::{PAddr, PageTableMut, PageTableRef, PhysMapper, Pte, VAddr, VmFlags, VmMeta};
+use core::ptr::NonNull;
+
+impl<Meta: VmMeta> PageTableRef<'_, Meta> {
+    /// 查询虚地址 `vaddr` 映射到的物理地址。
+    ///
+    /// 页表通过 `mapper` 访问。
+    /// 如果 `vaddr` 已映射，返回包括页内偏移的物理地址、叶子页表项的属性和叶子页表项的级别。
+    pub fn translate(
+        &self,
+        vaddr: VAddr<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Option<(PAddr<Meta>, VmFlags<Meta>, usize)> {
+        let mut visitor = TranslateVisitor { mapper, ans: None };
+        self.walk(Pos::new(vaddr.floor(), 0), &mut visitor);
+        visitor.ans.map(|(pte, level)| {
+            let offset = vaddr.val() & (Meta::bytes_in_page(level) - 1);
+            (
+                pte.leaf_ppn(level).base() + offset,
+                pte.leaf_flags(level),
+                level,
+            )
+        })
+    }
+}
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 查询虚地址 `vaddr` 映射到的物理地址。
+    ///
+    /// 见 [`PageTableRef::translate`]。
+    #[inline]
+    pub fn translate(
+        &self,
+        vaddr: VAddr<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Option<(PAddr<Meta>, VmFlags<Meta>, usize)> {
+        self.as_ref().translate(vaddr, mapper)
+    }
+}
+
+struct TranslateVisitor<'a, Meta: VmMeta, M> {
+    mapper: &'a M,
+    ans: Option<(Pte<Meta>, usize)>,
+}
+
+impl<Meta: VmMeta, M: PhysMapper<Meta>> Visitor<Meta> for TranslateVisitor<'_, Meta, M> {
+    #[inline]
+    fn arrive(&mut self, pte: Pte<Meta>, _target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            self.ans = Some((pte, 0));
+        }
+        Pos::stop()
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    #[inline]
+    fn block(&mut self, level: usize, pte: Pte<Meta>, _target: Pos<Meta>) -> Pos<Meta> {
+        if pte.is_valid() {
+            self.ans = Some((pte, level));
+        }
+        Pos::stop()
+    }
+}
+
+#[test]
+fn test_translate() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper, PPN, VPN,
+    };
+
+    let mut frames = Frames::<3>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+    pt.map(
+        VPN::new(0x200)..VPN::new(0x204),
+        PPN::new(0x8000),
+        flags,
+        0,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    pt.map(
+        VPN::new(0x400)..VPN::new(0x600),
+        PPN::new(0x10000),
+        flags,
+        1,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+
+    assert_eq!(
+        pt.translate(VAddr::new(0x20_1234), &IdentityMapper),
+        Some((PAddr::new(0x800_1234), flags, 0))
+    );
+    assert_eq!(
+        pt.translate(VAddr::new(0x45_6789), &IdentityMapper),
+        Some((PAddr::new(0x1005_6789), flags, 1))
+    );
+    assert_eq!(pt.translate(VAddr::new(0x20_4000), &IdentityMapper), None);
+    assert_eq!(pt.translate(VAddr::new(0x4000_0000), &IdentityMapper), None);
+}
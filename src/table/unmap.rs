@@ -0,0 +1,282 @@
+﻿use super::{map_child, skip, Decorator, Pos, Update};
+use crate::{
+    FrameAllocator, PageTableError, PageTableMut, PageTableRef, PhysMapper, Pte, VmMeta, PPN, VPN,
+};
+use core::{marker::PhantomData, ops::Range, ptr::NonNull};
+
+impl<Meta: VmMeta> PageTableMut<'_, Meta> {
+    /// 撤销 `range` 范围内的所有映射。
+    ///
+    /// 每个被撤销的叶子页表项以其起始虚页号、物理页号和级别报告给 `unmapped`，由调用者回收物理页。
+    /// 因撤销而变为完全无效的中间页表会从父页表中移除，并回收到 `alloc`，
+    /// 以其起始虚页号和指向它的页表项的级别报告给 `freed`，供调用者刷新页表缓存。
+    /// 调用前就已经完全无效的中间页表保持不变。页表通过 `mapper` 访问。
+    ///
+    /// 共享的页表项（见 [`share`](Self::share)）只断开链接，不报告也不回收它指向的子页表或物理页。
+    ///
+    /// 如果 `range` 只覆盖了一个大页的一部分，返回 [`PageTableError::HugePageConflict`]；
+    /// 只覆盖了一个共享页表项的一部分，返回 [`PageTableError::SharedTable`]。已经撤销的映射不会恢复。
+    pub fn unmap(
+        &mut self,
+        range: Range<VPN<Meta>>,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+        unmapped: impl FnMut(VPN<Meta>, PPN<Meta>, usize),
+        freed: impl FnMut(VPN<Meta>, usize),
+    ) -> Result<(), PageTableError> {
+        self.check_range(&range)?;
+        if range.start >= range.end {
+            return Ok(());
+        }
+        let mut visitor = UnmapVisitor {
+            end: range.end,
+            alloc,
+            mapper,
+            unmapped,
+            freed,
+            cleared: 0,
+            ans: Ok(()),
+            _phantom: PhantomData,
+        };
+        self.walk_mut(Pos::new(range.start, 0), &mut visitor);
+        visitor.ans
+    }
+}
+
+struct UnmapVisitor<'a, Meta: VmMeta, A, M, U, F> {
+    end: VPN<Meta>,
+    alloc: &'a mut A,
+    mapper: &'a M,
+    unmapped: U,
+    freed: F,
+    /// 第 `i` 位表示当前正在访问的 `i` 级页表中有页表项被清除。
+    cleared: usize,
+    ans: Result<(), PageTableError>,
+    _phantom: PhantomData<Meta>,
+}
+
+impl<Meta, A, M, U, F> Decorator<Meta> for UnmapVisitor<'_, Meta, A, M, U, F>
+where
+    Meta: VmMeta,
+    A: FrameAllocator<Meta>,
+    M: PhysMapper<Meta>,
+    U: FnMut(VPN<Meta>, PPN<Meta>, usize),
+    F: FnMut(VPN<Meta>, usize),
+{
+    fn arrive(&mut self, pte: &mut Pte<Meta>, target: Pos<Meta>) -> Pos<Meta> {
+        // 共享的页表项只断开链接
+        if pte.is_valid() && !pte.is_shared() {
+            (self.unmapped)(target.vpn, pte.leaf_ppn(target.level), target.level);
+        }
+        if pte.is_valid() {
+            self.cleared |= 1 << target.level;
+        }
+        *pte = Pte::ZERO;
+        skip(target.vpn, target.level, self.end, 0)
+    }
+
+    #[inline]
+    fn meet(
+        &mut self,
+        level: usize,
+        pte: Pte<Meta>,
+        target: Pos<Meta>,
+    ) -> Option<NonNull<Pte<Meta>>> {
+        map_child(self.mapper, level, pte, target)
+    }
+
+    fn block(&mut self, level: usize, pte: Pte<Meta>, target: Pos<Meta>) -> Update<Meta> {
+        if !pte.is_valid() {
+            return Update::Target(skip(target.vpn, level, self.end, 0));
+        }
+        // 大页或共享页表项完全在范围内才能撤销，转为访问页表项本身
+        let base = target.vpn.floor(level);
+        if base >= target.vpn && base + Meta::pages_in_page(level) <= self.end {
+            Update::Target(Pos::new(base, level))
+        } else {
+            self.ans = Err(if pte.is_shared() {
+                PageTableError::SharedTable
+            } else {
+                PageTableError::HugePageConflict
+            });
+            Update::Target(Pos::stop())
+        }
+    }
+
+    fn leave(&mut self, level: usize, pte: &mut Pte<Meta>, table: &PageTableRef<Meta>) {
+        // 只回收这次撤销清空的子页表
+        let cleared = self.cleared & (1 << (level - 1)) != 0;
+        self.cleared &= !(1 << (level - 1));
+        if cleared && table.is_empty() {
+            self.alloc.deallocate_one(pte.ppn());
+            *pte = Pte::ZERO;
+            self.cleared |= 1 << level;
+            (self.freed)(table.range().start, level);
+        }
+    }
+}
+
+#[test]
+fn test_unmap() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper, VmFlags,
+    };
+
+    let mut frames = Frames::<5>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+
+    pt.map(
+        VPN::new(0x200)..VPN::new(0x204),
+        PPN::new(0x8000),
+        flags,
+        0,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    pt.map(
+        VPN::new(0x400)..VPN::new(0x800),
+        PPN::new(0x10000),
+        flags,
+        1,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    pt.map(
+        VPN::new(1 << 18)..VPN::new((1 << 18) + 1),
+        PPN::new(0x9000),
+        flags,
+        0,
+        &mut frames,
+        &IdentityMapper,
+    )
+    .unwrap();
+    assert_eq!(frames.used(), 5);
+
+    // 部分覆盖大页
+    assert_eq!(
+        pt.unmap(
+            VPN::new(0x500)..VPN::new(0x600),
+            &mut frames,
+            &IdentityMapper,
+            |_, _, _| unreachable!(),
+            |_, _| unreachable!()
+        ),
+        Err(PageTableError::HugePageConflict)
+    );
+
+    let mut pages = [(VPN::ZERO, PPN::ZERO, 0); 8];
+    let mut len = 0;
+    pt.unmap(
+        VPN::new(0x202)..VPN::new(0x600),
+        &mut frames,
+        &IdentityMapper,
+        |vpn, ppn, level| {
+            pages[len] = (vpn, ppn, level);
+            len += 1;
+        },
+        |_, _| unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(
+        pages[..len],
+        [
+            (VPN::new(0x202), PPN::new(0x8002), 0),
+            (VPN::new(0x203), PPN::new(0x8003), 0),
+            (VPN::new(0x400), PPN::new(0x10000), 1),
+        ]
+    );
+    assert_eq!(frames.used(), 5);
+
+    // 回收空的中间页表
+    len = 0;
+    let mut tables = [(VPN::ZERO, 0); 4];
+    let mut freed = 0;
+    pt.unmap(
+        VPN::ZERO..VPN::new(2 << 18),
+        &mut frames,
+        &IdentityMapper,
+        |vpn, ppn, level| {
+            pages[len] = (vpn, ppn, level);
+            len += 1;
+        },
+        |vpn, level| {
+            tables[freed] = (vpn, level);
+            freed += 1;
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        pages[..len],
+        [
+            (VPN::new(0x200), PPN::new(0x8000), 0),
+            (VPN::new(0x201), PPN::new(0x8001), 0),
+            (VPN::new(0x600), PPN::new(0x10200), 1),
+            (VPN::new(1 << 18), PPN::new(0x9000), 0),
+        ]
+    );
+    assert_eq!(
+        tables[..freed],
+        [
+            (VPN::new(0x200), 1),
+            (VPN::ZERO, 2),
+            (VPN::new(1 << 18), 1),
+            (VPN::new(1 << 18), 2),
+        ]
+    );
+    assert_eq!(frames.used(), 1);
+    assert!(pt.is_empty());
+}
+
+#[test]
+fn test_unmap_empty_table() {
+    use crate::{
+        test_meta::{root_table, Frames, Sv39},
+        IdentityMapper, VmFlags,
+    };
+
+    let mut frames = Frames::<4>::new();
+    let mut pt: PageTableMut<Sv39> = unsafe { root_table(&mut frames) };
+    let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+
+    for vpn in [0x200, 0x400] {
+        pt.map(
+            VPN::new(vpn)..VPN::new(vpn + 1),
+            PPN::new(0x8000),
+            flags,
+            0,
+            &mut frames,
+            &IdentityMapper,
+        )
+        .unwrap();
+    }
+    assert_eq!(frames.used(), 4);
+
+    // 绕过 unmap 清空 0x200 所在的页表
+    let table = |pte: Pte<Sv39>, pos| IdentityMapper.map_table(pte.ppn(), pos);
+    let l1 = table(pt[0], Pos::new(VPN::ZERO, 1));
+    let l1 = unsafe { PageTableMut::<Sv39>::from_raw_parts(l1, VPN::ZERO, 1) };
+    let l0 = table(l1[1], Pos::new(VPN::new(0x200), 0));
+    let mut l0 = unsafe { PageTableMut::<Sv39>::from_raw_parts(l0, VPN::new(0x200), 0) };
+    l0[0] = Pte::ZERO;
+
+    // 只回收这次清空的页表，原本就空的页表保留
+    let mut freed = None;
+    pt.unmap(
+        VPN::ZERO..VPN::new(1 << 18),
+        &mut frames,
+        &IdentityMapper,
+        |vpn, _, _| assert_eq!(vpn, VPN::new(0x400)),
+        |vpn, level| {
+            assert!(freed.is_none());
+            freed = Some((vpn, level));
+        },
+    )
+    .unwrap();
+    assert_eq!(freed, Some((VPN::new(0x400), 1)));
+    assert_eq!(frames.used(), 3);
+    assert!(!pt.is_empty());
+}
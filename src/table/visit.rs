@@ -1,5 +1,5 @@
 ﻿use super::Pos;
-use crate::{PageTable, Pte, VmMeta};
+use crate::{PageTableMut, PageTableRef, Pte, VmMeta};
 use core::ptr::NonNull;
 
 /// `Meta` 方案的页表访问机制。
@@ -39,11 +39,20 @@ pub trait Decorator<Meta: VmMeta> {
 
     /// 在访问 `target` 的过程中，经过一个包括 `target` 的 `level` 级页表项 `pte`。
     ///
-    /// 以下两种情况会调用这个方法：
+    /// 以下三种情况会调用这个方法：
     ///
     /// - 访问到包含目标虚页的大页节点；
     /// - 访问到包含目标虚页的无效节点；
+    /// - 访问到包含目标虚页的共享节点，见 [`Pte::is_shared`]；
     fn block(&mut self, level: usize, pte: Pte<Meta>, target_hint: Pos<Meta>) -> Update<Meta>;
+
+    /// 离开 `level` 级页表项 `pte` 指向的子页表 `table`。
+    ///
+    /// 子页表遍历完成后调用，可以在这里修改 `pte` 或回收子页表。默认什么也不做。
+    #[inline]
+    fn leave(&mut self, level: usize, pte: &mut Pte<Meta>, table: &PageTableRef<Meta>) {
+        let _ = (level, pte, table);
+    }
 }
 
 /// 遍历中断时的更新方案。
@@ -56,7 +65,7 @@ pub enum Update<Meta: VmMeta> {
 
 /// 递归遍历。
 pub(super) fn walk_inner<Meta: VmMeta>(
-    table: &PageTable<Meta>,
+    table: &PageTableRef<Meta>,
     visitor: &mut impl Visitor<Meta>,
     target: &mut Pos<Meta>,
 ) {
@@ -75,9 +84,9 @@ pub(super) fn walk_inner<Meta: VmMeta>(
                 match visitor.meet(level, pte, *target) {
                     Some(ptr) => {
                         let table = unsafe {
-                            PageTable::from_raw_parts(
+                            PageTableRef::from_raw_parts(
                                 ptr,
-                                range.start + index * Meta::pages_in_table(level - 1),
+                                range.start + index as u64 * Meta::pages_in_table(level - 1),
                                 level - 1,
                             )
                         };
@@ -100,7 +109,7 @@ pub(super) fn walk_inner<Meta: VmMeta>(
 
 /// 递归遍历。
 pub(super) fn walk_inner_mut<Meta: VmMeta>(
-    table: &mut PageTable<Meta>,
+    table: &mut PageTableMut<Meta>,
     visitor: &mut impl Decorator<Meta>,
     target: &mut Pos<Meta>,
 ) {
@@ -114,18 +123,19 @@ pub(super) fn walk_inner_mut<Meta: VmMeta>(
         let pte = &mut table.mem[index];
         // 目标节点等级比当前低需要查页表
         if level > target.level {
-            // 有效且不是叶子的页表项是子页表
-            if pte.is_valid() && !pte.is_leaf() {
+            // 有效且不是叶子的页表项是子页表，共享的子页表不能修改
+            if pte.is_valid() && !pte.is_leaf() && !pte.is_shared() {
                 match visitor.meet(level, *pte, *target) {
                     Some(ptr) => {
                         let mut table = unsafe {
-                            PageTable::from_raw_parts(
+                            PageTableMut::from_raw_parts(
                                 ptr,
-                                range.start + index * Meta::pages_in_table(level - 1),
+                                range.start + index as u64 * Meta::pages_in_table(level - 1),
                                 level - 1,
                             )
                         };
                         walk_inner_mut(&mut table, visitor, target);
+                        visitor.leave(level, pte, &table.as_ref());
                     }
                     None => *target = Pos::stop(),
                 }
@@ -139,13 +149,14 @@ pub(super) fn walk_inner_mut<Meta: VmMeta>(
                     Update::Pte(new, ptr) => {
                         *pte = new;
                         let mut table = unsafe {
-                            PageTable::from_raw_parts(
+                            PageTableMut::from_raw_parts(
                                 ptr,
-                                range.start + index * Meta::pages_in_table(level - 1),
+                                range.start + index as u64 * Meta::pages_in_table(level - 1),
                                 level - 1,
                             )
                         };
                         walk_inner_mut(&mut table, visitor, target);
+                        visitor.leave(level, pte, &table.as_ref());
                     }
                 }
             }
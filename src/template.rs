@@ -0,0 +1,232 @@
+﻿use crate::{
+    table::zero_table, FrameAllocator, PageTableError, PageTableMut, PageTableRef, PhysMapper, Pos,
+    VmMeta, PPN, VPN,
+};
+use core::ops::Range;
+
+/// 共享部分映射的根页表模板。
+///
+/// 模板根页表中 `range` 范围的页表项以共享的方式链接到所有登记的根页表中，
+/// 典型用途是让所有进程的根页表共享内核的高半部分映射。
+/// 共享的子页表和物理页属于模板，登记的根页表撤销映射或销毁时不会回收它们，见 [`PageTableMut::share`]。
+///
+/// 最多登记 `N` 个根页表。
+pub struct SharedTemplate<Meta: VmMeta, const N: usize> {
+    template: PPN<Meta>,
+    range: Range<VPN<Meta>>,
+    roots: [PPN<Meta>; N],
+    len: usize,
+}
+
+impl<Meta: VmMeta, const N: usize> SharedTemplate<Meta, N> {
+    /// 以 `template` 为模板根页表，共享 `range` 范围的映射。
+    ///
+    /// `range` 必须按根页表的页表项对齐。
+    ///
+    /// # Safety
+    ///
+    /// `template` 必须是一个有效的根页表，并且在模板的生命周期内保持有效。
+    /// 通过 [`create`](Self::create) 登记的根页表在注销前不能回收，见 [`register`](Self::register)。
+    pub unsafe fn new(
+        template: PPN<Meta>,
+        range: Range<VPN<Meta>>,
+    ) -> Result<Self, PageTableError> {
+        if range.end.val() > Meta::pages_in_table(Meta::MAX_LEVEL) {
+            return Err(PageTableError::AddressOutOfRange);
+        }
+        let align = Meta::pages_in_page(Meta::MAX_LEVEL);
+        if (range.start.val() | range.end.val()) & (align - 1) != 0 {
+            return Err(PageTableError::Misaligned);
+        }
+        Ok(Self {
+            template,
+            range,
+            roots: [PPN::ZERO; N],
+            len: 0,
+        })
+    }
+
+    /// 模板根页表的物理页号。
+    #[inline]
+    pub const fn template(&self) -> PPN<Meta> {
+        self.template
+    }
+
+    /// 共享的虚页范围。
+    #[inline]
+    pub fn range(&self) -> Range<VPN<Meta>> {
+        self.range.clone()
+    }
+
+    /// 已登记的根页表。
+    #[inline]
+    pub fn roots(&self) -> &[PPN<Meta>] {
+        &self.roots[..self.len]
+    }
+
+    /// 从 `alloc` 分配一个新的根页表，链接共享的映射并登记。
+    ///
+    /// 页表通过 `mapper` 访问。如果 `mapper` 不能直接访问物理页，`alloc` 分配的页帧必须已经清零。
+    pub fn create(
+        &mut self,
+        alloc: &mut impl FrameAllocator<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<PPN<Meta>, PageTableError> {
+        if self.len == N {
+            return Err(PageTableError::RegistryFull);
+        }
+        let root = alloc.allocate_one().ok_or(PageTableError::OutOfFrames)?;
+        unsafe { zero_table(mapper, root, Meta::MAX_LEVEL) };
+        match unsafe { self.register(root, mapper) } {
+            Ok(()) => Ok(root),
+            Err(e) => {
+                alloc.deallocate_one(root);
+                Err(e)
+            }
+        }
+    }
+
+    /// 将共享的映射链接到根页表 `root` 并登记。
+    ///
+    /// 链接失败时不登记，见 [`PageTableMut::share`]。
+    ///
+    /// # Safety
+    ///
+    /// `root` 必须是一个有效的根页表，并且在注销前保持有效，因为 [`propagate`](Self::propagate) 会写入所有登记的根页表。
+    pub unsafe fn register(
+        &mut self,
+        root: PPN<Meta>,
+        mapper: &impl PhysMapper<Meta>,
+    ) -> Result<(), PageTableError> {
+        if self.len == N {
+            return Err(PageTableError::RegistryFull);
+        }
+        self.share(root, mapper)?;
+        self.roots[self.len] = root;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// 注销根页表 `root`。
+    ///
+    /// 不修改 `root` 中已经链接的页表项。如果 `root` 已登记，返回 `true`。
+    pub fn unregister(&mut self, root: PPN<Meta>) -> bool {
+        match self.roots().iter().position(|r| *r == root) {
+            Some(i) => {
+                self.len -= 1;
+                self.roots.swap(i, self.len);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 将模板中新增的页表项传播到所有登记的根页表。
+    ///
+    /// 模板的共享范围内新建了最高级页表项后调用。遇到错误时停止，已经传播的根页表不会恢复。
+    pub fn propagate(&mut self, mapper: &impl PhysMapper<Meta>) -> Result<(), PageTableError> {
+        for i in 0..self.len {
+            self.share(self.roots[i], mapper)?;
+        }
+        Ok(())
+    }
+
+    /// 将共享的映射链接到根页表 `root`。
+    fn share(&self, root: PPN<Meta>, mapper: &impl PhysMapper<Meta>) -> Result<(), PageTableError> {
+        let pos = Pos::new(VPN::ZERO, Meta::MAX_LEVEL);
+        let template = unsafe { PageTableRef::from_root(mapper.map_table(self.template, pos)) };
+        let mut pt = unsafe { PageTableMut::from_root(mapper.map_table(root, pos)) };
+        pt.share(&template, self.range.clone())
+    }
+}
+
+#[test]
+fn test_shared_template() {
+    use crate::{
+        test_meta::{frame_ptr, Frames, Sv39},
+        IdentityMapper, VAddr, VmFlags,
+    };
+
+    fn map(pt: &mut PageTableMut<Sv39>, vpn: u64, ppn: u64, frames: &mut Frames<9>) {
+        let flags = unsafe { VmFlags::<Sv39>::from_raw(0b1011) };
+        let vpn = VPN::new(vpn);
+        pt.map(
+            vpn..vpn + 1,
+            PPN::new(ppn),
+            flags,
+            0,
+            frames,
+            &IdentityMapper,
+        )
+        .unwrap()
+    }
+
+    let mut frames = Frames::<9>::new();
+    let root = frames.allocate_one().unwrap();
+    let mut kernel = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(root)) };
+    map(&mut kernel, 256 << 18, 0x8000, &mut frames);
+
+    let mut template = unsafe {
+        SharedTemplate::<Sv39, 2>::new(root, VPN::new(256 << 18)..VPN::new(512 << 18)).unwrap()
+    };
+    let root_a = template.create(&mut frames, &IdentityMapper).unwrap();
+    let root_b = template.create(&mut frames, &IdentityMapper).unwrap();
+    assert_eq!(
+        template.create(&mut frames, &IdentityMapper),
+        Err(PageTableError::RegistryFull)
+    );
+    let mut a = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(root_a)) };
+    let mut b = unsafe { PageTableMut::<Sv39>::from_root(frame_ptr(root_b)) };
+    let kernel_vaddr = |i: u64| VAddr::<Sv39>::new(((256 + i) << 30) + 0x123);
+    assert!(a.translate(kernel_vaddr(0), &IdentityMapper).is_some());
+
+    // 传播新增的最高级页表项
+    map(&mut a, 0x200, 0x9000, &mut frames);
+    map(&mut kernel, 257 << 18, 0x8001, &mut frames);
+    assert!(b.translate(kernel_vaddr(1), &IdentityMapper).is_none());
+    template.propagate(&IdentityMapper).unwrap();
+    assert!(b.translate(kernel_vaddr(1), &IdentityMapper).is_some());
+    assert_eq!(frames.used(), 9);
+
+    // 不能修改共享的页表项
+    let vpn = VPN::new(256 << 18);
+    assert_eq!(
+        b.unmap(
+            vpn..vpn + 1,
+            &mut frames,
+            &IdentityMapper,
+            |_, _, _| {},
+            |_, _| {}
+        ),
+        Err(PageTableError::SharedTable)
+    );
+
+    // 只断开共享的页表项
+    let mut unmapped = 0;
+    let range = a.range();
+    a.unmap(
+        range,
+        &mut frames,
+        &IdentityMapper,
+        |vpn, _, _| {
+            assert_eq!(vpn, VPN::new(0x200));
+            unmapped += 1;
+        },
+        |_, _| {},
+    )
+    .unwrap();
+    assert_eq!(unmapped, 1);
+    assert!(a.is_empty());
+    assert_eq!(frames.used(), 7);
+    assert!(kernel.translate(kernel_vaddr(0), &IdentityMapper).is_some());
+    assert!(template.unregister(root_a));
+    assert_eq!(template.roots(), [root_b]);
+
+    // 复制出的页表不会自动登记
+    let child = b
+        .fork(&mut frames, &IdentityMapper, |_, _, _| {}, |_, _| {})
+        .unwrap();
+    assert_eq!(template.roots(), [root_b]);
+    assert_eq!(unsafe { template.register(child, &IdentityMapper) }, Ok(()));
+    assert_eq!(template.roots(), [root_b, child]);
+}